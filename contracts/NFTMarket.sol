@@ -1,45 +1,125 @@
 // SPDX-License-Identifier: MIT
 // pragma solidity ^0.8.4;
 
-import "@openzeppelin/contracts/utils/Counters.sol";
-import "@openzeppelin/contracts/token/ERC721/extensions/ERC721URIStorage.sol";
-import "@openzeppelin/contracts/token/ERC721/ERC721.sol";
+import "@openzeppelin/contracts/proxy/Proxy.sol";
+import "@openzeppelin/contracts/proxy/utils/UUPSUpgradeable.sol";
+import "@openzeppelin/contracts/utils/cryptography/SignatureChecker.sol";
+import "@openzeppelin/contracts/utils/cryptography/MerkleProof.sol";
+
+import "./NFTMarketplaceCore.sol";
+import "./NFT.sol";
 
 import "hardhat/console.sol";
 
-contract NFTMarketplace is ERC721URIStorage {
+/* Deployed behind a UUPS proxy (NFTMarketplaceProxy), which holds the escrowed tokens and all marketplace state */
+/* Its bases work unchanged behind the proxy: ReentrancyGuard treats an unset status as not entered */
+/* and EIP712 rebuilds its domain separator for the proxy address */
+/* Editions, bundles, auctions, offers and signed orders live in NFTMarketplaceExtension to stay under the EIP-170 size limit */
+/* Calls to them fall through to the extension, which runs in the proxy's storage like this contract */
+contract NFTMarketplace is
+  NFTMarketplaceCore,
+  UUPSUpgradeable,
+  Proxy
+{
     using Counters for Counters.Counter;
-    Counters.Counter private _tokenIds;
-    Counters.Counter private _itemsSold;
+    using EnumerableSet for EnumerableSet.UintSet;
+
+    /* Kept in the implementation's code, so an upgrade swaps the extension along with it */
+    address private immutable extension;
 
-    uint256 listingPrice = 0.025 ether;
-    address payable owner;
+    /* Locks the implementation itself, so only the proxy can ever be initialized */
+    constructor(address _extension) EIP712("NFTMarketplace", "1") initializer {
+      extension = _extension;
+    }
 
-    mapping(uint256 => MarketItem) private idToMarketItem;
+    /* Sets up the marketplace behind its proxy, making the caller its owner with every role */
+    function initialize() public initializer {
+      listingPrice = 0.025 ether;
+      defaultRoyaltyFraction = 500;
+      maxBatchSize = 100;
+      owner = payable(msg.sender);
+      feeRecipient = payable(msg.sender);
+      _grantRole(DEFAULT_ADMIN_ROLE, msg.sender);
+      _grantRole(FEE_MANAGER_ROLE, msg.sender);
+      _grantRole(PAUSER_ROLE, msg.sender);
+      _grantRole(MODERATOR_ROLE, msg.sender);
+      _grantRole(UPGRADER_ROLE, msg.sender);
+    }
 
-    struct MarketItem {
-      uint256 tokenId;
-      address payable seller;
-      address payable owner;
-      uint256 price;
-      bool sold;
+    /* Returns the contract functions not implemented here are delegated to */
+    function getExtension() public view returns (address) {
+      return extension;
     }
 
-    event MarketItemCreated (
-      uint256 indexed tokenId,
-      address seller,
-      address owner,
-      uint256 price,
-      bool sold
-    );
+    function _implementation() internal view virtual override returns (address) {
+      return extension;
+    }
+
+    /* Only upgraders can point the proxy at a new implementation */
+    function _authorizeUpgrade(address) internal virtual override {
+      require(hasRole(UPGRADER_ROLE, msg.sender), "Only upgrader can upgrade the marketplace.");
+    }
+
+    /* Starts handing the marketplace over to a new owner, who has to accept it before it takes effect */
+    function transferOwnership(address newOwner) public {
+      require(owner == msg.sender, "Only marketplace owner can transfer ownership.");
+      require(newOwner != msg.sender, "Marketplace is already owned by this address");
+      pendingOwner = newOwner;
+      emit OwnershipTransferStarted(owner, newOwner);
+    }
 
-    constructor() ERC721("Metaverse Tokens", "METT") {
+    /* Completes an ownership transfer, moving the admin role and every operational role the previous owner held to the caller */
+    /* Roles the previous owner granted to other accounts stay with them until the new owner revokes them */
+    function acceptOwnership() public {
+      require(pendingOwner == msg.sender, "Only the pending owner can accept ownership");
+      address previousOwner = owner;
       owner = payable(msg.sender);
+      pendingOwner = address(0);
+      bytes32[5] memory roles = [DEFAULT_ADMIN_ROLE, FEE_MANAGER_ROLE, PAUSER_ROLE, MODERATOR_ROLE, UPGRADER_ROLE];
+      for (uint i = 0; i < roles.length; i++) {
+        if (hasRole(roles[i], previousOwner)) {
+          _grantRole(roles[i], msg.sender);
+          _revokeRole(roles[i], previousOwner);
+        }
+      }
+      emit OwnershipTransferred(previousOwner, msg.sender);
+    }
+
+    /* Returns the current owner of the marketplace */
+    function getOwner() public view returns (address) {
+      return owner;
+    }
+
+    /* Returns the address an ownership transfer is waiting on, if any */
+    function getPendingOwner() public view returns (address) {
+      return pendingOwner;
+    }
+
+    /* The owner keeps the admin role until ownership is transferred, so it cannot be revoked or renounced directly */
+    function _revokeRole(bytes32 role, address account) internal virtual override {
+      require(role != DEFAULT_ADMIN_ROLE || account != owner, "Ownership must be transferred to remove the owner's admin role");
+      super._revokeRole(role, account);
+    }
+
+    /* Pauses or resumes minting, listing and buying independently */
+    /* Cancelling listings, settling auctions and withdrawing funds stay available while paused */
+    function updatePaused(bool _mintingPaused, bool _listingPaused, bool _buyingPaused) public {
+      require(hasRole(PAUSER_ROLE, msg.sender), "Only pauser can update paused state.");
+      mintingPaused = _mintingPaused;
+      listingPaused = _listingPaused;
+      buyingPaused = _buyingPaused;
+      emit PausedUpdated(_mintingPaused, _listingPaused, _buyingPaused);
+    }
+
+    /* Returns whether minting, listing and buying are paused */
+    function getPaused() public view returns (bool, bool, bool) {
+      return (mintingPaused, listingPaused, buyingPaused);
     }
 
     /* Updates the listing price of the contract */
     function updateListingPrice(uint _listingPrice) public payable {
-      require(owner == msg.sender, "Only marketplace owner can update listing price.");
+      require(hasRole(FEE_MANAGER_ROLE, msg.sender), "Only fee manager can update listing price.");
+      emit ListingPriceUpdated(listingPrice, _listingPrice);
       listingPrice = _listingPrice;
     }
 
@@ -48,135 +128,563 @@ contract NFTMarketplace is ERC721URIStorage {
       return listingPrice;
     }
 
+    /* Updates the fee, in basis points of the sale price, taken by the marketplace on every sale */
+    function updateMarketplaceFee(uint96 _marketplaceFee) public {
+      require(hasRole(FEE_MANAGER_ROLE, msg.sender), "Only fee manager can update marketplace fee.");
+      require(_marketplaceFee <= maxMarketplaceFee, "Marketplace fee must not exceed the maximum fee");
+      marketplaceFee = _marketplaceFee;
+      emit MarketplaceFeeUpdated(_marketplaceFee);
+    }
+
+    /* Returns the fee, in basis points of the sale price, taken by the marketplace on every sale */
+    function getMarketplaceFee() public view returns (uint96) {
+      return marketplaceFee;
+    }
+
+    /* Updates the address the listing price and marketplace fees are paid to */
+    function updateFeeRecipient(address payable _feeRecipient) public {
+      require(hasRole(FEE_MANAGER_ROLE, msg.sender), "Only fee manager can update fee recipient.");
+      require(_feeRecipient != address(0), "Fee recipient cannot be the zero address");
+      feeRecipient = _feeRecipient;
+      emit FeeRecipientUpdated(_feeRecipient);
+    }
+
+    /* Returns the address the listing price and marketplace fees are paid to */
+    function getFeeRecipient() public view returns (address) {
+      return feeRecipient;
+    }
+
+    /* Sets the NFT contract the marketplace mints its own tokens on */
+    function updateTokenContract(address _tokenContract) public {
+      require(hasRole(DEFAULT_ADMIN_ROLE, msg.sender), "Only marketplace owner can update token contract.");
+      tokenContract = _tokenContract;
+    }
+
+    /* Returns the NFT contract the marketplace mints its own tokens on */
+    function getTokenContract() public view returns (address) {
+      return tokenContract;
+    }
+
+    /* Updates the default royalty, in basis points, paid to creators on every sale */
+    function updateDefaultRoyalty(uint96 _royaltyFraction) public {
+      require(hasRole(FEE_MANAGER_ROLE, msg.sender), "Only fee manager can update default royalty.");
+      require(_royaltyFraction <= maxRoyaltyFraction, "Royalty must leave room for the maximum marketplace fee");
+      defaultRoyaltyFraction = _royaltyFraction;
+    }
+
+    /* Returns the default royalty, in basis points, applied to newly minted tokens */
+    function getDefaultRoyalty() public view returns (uint96) {
+      return defaultRoyaltyFraction;
+    }
+
+    /* Adds or removes an ERC-20 token from the currencies listings can be priced in */
+    function updateCurrencyAllowed(address currency, bool allowed) public {
+      require(hasRole(MODERATOR_ROLE, msg.sender), "Only moderator can update allowed currencies.");
+      require(currency != address(0), "Native currency is always allowed");
+      allowedCurrencies[currency] = allowed;
+      emit CurrencyAllowlistUpdated(currency, allowed);
+    }
+
     /* Mints a token and lists it in the marketplace */
     function createToken(string memory tokenURI, uint256 price) public payable returns (uint) {
-      _tokenIds.increment();
-      uint256 newTokenId = _tokenIds.current();
+      return createTokenWithRoyalty(tokenURI, price, defaultRoyaltyFraction);
+    }
+
+    /* Mints a token with a creator royalty overriding the default and lists it in the marketplace */
+    function createTokenWithRoyalty(
+      string memory tokenURI,
+      uint256 price,
+      uint96 royaltyFraction
+      ) public payable nonReentrant returns (uint) {
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(royaltyFraction <= maxRoyaltyFraction, "Royalty must leave room for the maximum marketplace fee");
+      return mintMarketItem(tokenURI, price, royaltyFraction);
+    }
+
+    /* Mints a batch of tokens and lists each of them in the marketplace */
+    /* Charges the listing price once per token */
+    function createTokens(
+      string[] memory tokenURIs,
+      uint256[] memory prices
+      ) public payable nonReentrant returns (uint[] memory) {
+      require(tokenURIs.length == prices.length, "Token URIs and prices must have the same length");
+      require(tokenURIs.length > 0, "Batch must contain at least one token");
+      require(tokenURIs.length <= maxBatchSize, "Batch exceeds the maximum batch size");
+      require(msg.value == listingPrice * tokenURIs.length, "Price must be equal to listing price times the number of tokens");
+
+      uint[] memory newTokenIds = new uint[](tokenURIs.length);
+      for (uint i = 0; i < tokenURIs.length; i++) {
+        newTokenIds[i] = mintMarketItem(tokenURIs[i], prices[i], defaultRoyaltyFraction);
+      }
+      return newTokenIds;
+    }
+
+    /* Updates the maximum number of tokens createTokens mints in one call */
+    function updateMaxBatchSize(uint256 _maxBatchSize) public {
+      require(hasRole(DEFAULT_ADMIN_ROLE, msg.sender), "Only marketplace owner can update max batch size.");
+      require(_maxBatchSize > 0, "Max batch size must be greater than zero");
+      maxBatchSize = _maxBatchSize;
+    }
+
+    /* Returns the maximum number of tokens createTokens mints in one call */
+    function getMaxBatchSize() public view returns (uint256) {
+      return maxBatchSize;
+    }
+
+    /* Returns the EIP-712 digest a creator signs for a mint voucher */
+    function hashMintVoucher(MintVoucher calldata voucher) public view returns (bytes32) {
+      return _hashTypedDataV4(keccak256(abi.encode(
+        MINT_VOUCHER_TYPEHASH,
+        voucher.creator,
+        keccak256(bytes(voucher.tokenURI)),
+        voucher.price,
+        voucher.nonce
+      )));
+    }
+
+    /* Lazily mints a token to the buyer from a voucher signed by its creator */
+    /* The creator is paid the voucher price minus the listing price, which goes to the marketplace */
+    function redeemVoucher(MintVoucher calldata voucher, bytes calldata signature) public payable nonReentrant returns (uint) {
+      require(!mintingPaused, "Minting is paused");
+      require(!buyingPaused, "Buying is paused");
+      require(tokenContract != address(0), "Token contract is not set");
+      require(!usedVoucherNonces[voucher.creator][voucher.nonce], "Voucher has already been redeemed or cancelled");
+      require(SignatureChecker.isValidSignatureNow(voucher.creator, hashMintVoucher(voucher), signature), "Invalid voucher signature");
+      uint256 feeAmount = voucher.price * marketplaceFee / 10000;
+      uint256 fees = listingPrice + feeAmount;
+      require(voucher.price >= fees, "Voucher price must cover the marketplace fees");
+      require(msg.value == voucher.price, "Please submit the asking price in order to complete the purchase");
+
+      usedVoucherNonces[voucher.creator][voucher.nonce] = true;
+      uint256 newTokenId = NFT(tokenContract).mint(msg.sender, voucher.tokenURI, voucher.creator, defaultRoyaltyFraction);
+      MarketItem storage item = idToMarketItem[itemIdFor(tokenContract, newTokenId)];
+      updateItemHolders(item, msg.sender, address(0));
+      item.price = voucher.price;
+      item.sold = true;
+      creditFunds(feeRecipient, fees);
+      creditFunds(voucher.creator, voucher.price - fees);
+      emitSale(item.itemId, address(0), msg.sender, voucher.creator, voucher.price, feeAmount, 0);
+      emit VoucherRedeemed(newTokenId, voucher.creator, msg.sender, voucher.price, voucher.nonce);
+      return newTokenId;
+    }
 
-      _mint(msg.sender, newTokenId);
-      _setTokenURI(newTokenId, tokenURI);
-      createMarketItem(newTokenId, price);
+    /* Cancels an unredeemed mint voucher of the caller by its nonce */
+    function cancelMintVoucher(uint256 nonce) public {
+      usedVoucherNonces[msg.sender][nonce] = true;
+      emit VoucherCancelled(msg.sender, nonce);
+    }
+
+    /* Returns whether a creator's voucher nonce has been redeemed or cancelled */
+    function isVoucherNonceUsed(address creator, uint256 nonce) public view returns (bool) {
+      return usedVoucherNonces[creator][nonce];
+    }
+
+    function mintMarketItem(
+      string memory tokenURI,
+      uint256 price,
+      uint96 royaltyFraction
+    ) private returns (uint) {
+      require(!mintingPaused, "Minting is paused");
+      require(tokenContract != address(0), "Token contract is not set");
+      require(price > 0, "Price must be at least 1 wei");
+      uint256 newTokenId = NFT(tokenContract).mint(address(this), tokenURI, msg.sender, royaltyFraction);
+      recordListing(tokenContract, newTokenId, price, address(0));
       return newTokenId;
     }
 
+    /* Lists a token of any ERC-721 collection in the marketplace */
+    /* The marketplace has to be approved to transfer the token into escrow */
     function createMarketItem(
+      address nftContract,
       uint256 tokenId,
       uint256 price
-    ) private {
-      require(price > 0, "Price must be at least 1 wei");
+      ) public payable nonReentrant returns (uint) {
       require(msg.value == listingPrice, "Price must be equal to listing price");
+      return listMarketItem(nftContract, tokenId, price, 0);
+    }
 
-      idToMarketItem[tokenId] =  MarketItem(
-        tokenId,
-        payable(msg.sender),
-        payable(address(this)),
-        price,
-        false
-      );
+    /* Lists a token of any ERC-721 collection until an expiry, after which it can no longer be bought */
+    /* Anyone can then return the expired item to its seller with reclaimExpiredItem */
+    function createMarketItemWithExpiry(
+      address nftContract,
+      uint256 tokenId,
+      uint256 price,
+      uint256 expiresAt
+      ) public payable nonReentrant returns (uint) {
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(expiresAt > block.timestamp, "Expiry must be in the future");
+      return listMarketItem(nftContract, tokenId, price, expiresAt);
+    }
+
+    /* Lists a token for a private sale that only the designated buyer, or a buyer in the Merkle root, can purchase */
+    /* Private sales are hidden from fetchMarketItems; buyers in the root purchase with createPrivateMarketSale */
+    function createPrivateSale(
+      address nftContract,
+      uint256 tokenId,
+      uint256 price,
+      address buyer,
+      bytes32 buyersRoot
+      ) public payable nonReentrant returns (uint) {
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(buyer != address(0) || buyersRoot != bytes32(0), "Private sale needs a buyer or a Merkle root of buyers");
+      uint256 itemId = listMarketItem(nftContract, tokenId, price, 0);
+      idToPrivateSale[itemId] = PrivateSale(buyer, buyersRoot);
+      if (buyer != address(0)) {
+        reservedListings[buyer].add(itemId);
+      }
+      if (buyersRoot != bytes32(0)) {
+        rootGatedListings.add(itemId);
+      }
+      emit PrivateSaleCreated(itemId, buyer, msg.sender, buyersRoot, price);
+      return itemId;
+    }
 
-      _transfer(msg.sender, address(this), tokenId);
-      emit MarketItemCreated(
-        tokenId,
-        msg.sender,
-        address(this),
-        price,
-        false
+    /* Returns whether a buyer may purchase a listing, given their Merkle proof for root-gated private sales */
+    function isAllowedBuyer(uint256 itemId, address buyer, bytes32[] memory proof) public view returns (bool) {
+      PrivateSale storage privateSale = idToPrivateSale[itemId];
+      if (!isPrivateSale(itemId)) {
+        return true;
+      }
+      return buyer == privateSale.buyer || (
+        privateSale.buyersRoot != bytes32(0)
+        && MerkleProof.verify(proof, privateSale.buyersRoot, keccak256(abi.encodePacked(buyer)))
       );
     }
 
+    function isPrivateSale(uint256 itemId) private view returns (bool) {
+      return idToPrivateSale[itemId].buyer != address(0) || idToPrivateSale[itemId].buyersRoot != bytes32(0);
+    }
+
+    function listMarketItem(
+      address nftContract,
+      uint256 tokenId,
+      uint256 price,
+      uint256 expiresAt
+    ) private returns (uint) {
+      require(price > 0, "Price must be at least 1 wei");
+      uint256 itemId = escrowItem(nftContract, tokenId, price, address(0));
+      idToMarketItem[itemId].expiresAt = expiresAt;
+      return itemId;
+    }
+
+    /* Returns the id of the market item tracking a token, or zero if it was never traded here */
+    function getItemId(address nftContract, uint256 tokenId) public view returns (uint256) {
+      return tokenToItemId[nftContract][tokenId];
+    }
+
+    /* Returns a market item by id, including ERC-1155 edition listings and bundles */
+    function fetchMarketItem(uint256 itemId) public view returns (MarketItem memory) {
+      return idToMarketItem[itemId];
+    }
+
     /* allows someone to resell a token they have purchased */
-    function resellToken(uint256 tokenId, uint256 price) public payable {
-      require(idToMarketItem[tokenId].owner == msg.sender, "Only item owner can perform this operation");
-      require(msg.value == listingPrice, "Price must be equal to listing price");
-      idToMarketItem[tokenId].sold = false;
-      idToMarketItem[tokenId].price = price;
-      idToMarketItem[tokenId].seller = payable(msg.sender);
-      idToMarketItem[tokenId].owner = payable(address(this));
-      _itemsSold.decrement();
+    function resellToken(address nftContract, uint256 tokenId, uint256 price) public payable {
+      resellTokenForCurrency(nftContract, tokenId, price, address(0));
+    }
 
-      _transfer(msg.sender, address(this), tokenId);
+    /* allows someone to resell a token they have purchased, priced in an allowed ERC-20 token */
+    /* The zero address as currency prices the item in the native currency */
+    function resellTokenForCurrency(
+      address nftContract,
+      uint256 tokenId,
+      uint256 price,
+      address currency
+      ) public payable nonReentrant {
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(price > 0, "Price must be at least 1 wei");
+      require(isCurrencyAllowed(currency), "Currency is not allowed");
+      escrowItem(nftContract, tokenId, price, currency);
     }
 
     /* Creates the sale of a marketplace item */
     /* Transfers ownership of the item, as well as funds between parties */
     function createMarketSale(
+      address nftContract,
       uint256 tokenId
-      ) public payable {
-      uint price = idToMarketItem[tokenId].price;
-      address seller = idToMarketItem[tokenId].seller;
-      require(msg.value == price, "Please submit the asking price in order to complete the purchase");
-      idToMarketItem[tokenId].owner = payable(msg.sender);
-      idToMarketItem[tokenId].sold = true;
-      idToMarketItem[tokenId].seller = payable(address(0));
-      _itemsSold.increment();
-      _transfer(address(this), msg.sender, tokenId);
-      payable(owner).transfer(listingPrice);
-      payable(seller).transfer(msg.value);
-    }
-
-    /* Returns all unsold market items */
-    function fetchMarketItems() public view returns (MarketItem[] memory) {
-      uint itemCount = _tokenIds.current();
-      uint unsoldItemCount = _tokenIds.current() - _itemsSold.current();
-      uint currentIndex = 0;
+      ) public payable nonReentrant {
+      buyMarketItem(nftContract, tokenId, new bytes32[](0));
+    }
 
-      MarketItem[] memory items = new MarketItem[](unsoldItemCount);
-      for (uint i = 0; i < itemCount; i++) {
-        if (idToMarketItem[i + 1].owner == address(this)) {
-          uint currentId = i + 1;
-          MarketItem storage currentItem = idToMarketItem[currentId];
-          items[currentIndex] = currentItem;
-          currentIndex += 1;
-        }
-      }
-      return items;
+    /* Buys a private sale open to a Merkle root of buyers, proving the caller is one of them */
+    function createPrivateMarketSale(
+      address nftContract,
+      uint256 tokenId,
+      bytes32[] calldata proof
+      ) public payable nonReentrant {
+      buyMarketItem(nftContract, tokenId, proof);
     }
 
-    /* Returns only items that a user has purchased */
-    function fetchMyNFTs() public view returns (MarketItem[] memory) {
-      uint totalItemCount = _tokenIds.current();
-      uint itemCount = 0;
-      uint currentIndex = 0;
+    function buyMarketItem(address nftContract, uint256 tokenId, bytes32[] memory proof) private {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      require(idToMarketItem[itemId].owner == address(this), "Item is not listed");
+      require(idToAuction[itemId].endTime == 0, "Item is listed as an auction");
+      require(!isExpired(idToMarketItem[itemId]), "Listing has expired");
+      require(isAllowedBuyer(itemId, msg.sender, proof), "Item is reserved for another buyer");
+      uint price = currentPrice(itemId);
+      address currency = idToMarketItem[itemId].currency;
+      if (currency != address(0)) {
+        require(allowedCurrencies[currency], "Currency is not allowed");
+        require(msg.value == 0, "Item is priced in an ERC-20 currency");
+      } else if (idToDutchAuction[itemId].duration != 0) {
+        require(msg.value >= price, "Please submit at least the current price in order to complete the purchase");
+      } else {
+        require(msg.value == price, "Please submit the asking price in order to complete the purchase");
+      }
+      executeSale(itemId, price);
+      if (msg.value > price) {
+        creditFunds(msg.sender, msg.value - price);
+      }
+    }
 
-      for (uint i = 0; i < totalItemCount; i++) {
-        if (idToMarketItem[i + 1].owner == msg.sender) {
-          itemCount += 1;
+    /* Buys several listed items in one transaction, charging the summed price of the native-currency ones */
+    /* With partialFill, items that are no longer for sale or cost more than expected are skipped and refunded */
+    /* Otherwise the whole purchase reverts if any item cannot be bought */
+    function createMarketSales(
+      address[] calldata nftContracts,
+      uint256[] calldata tokenIds,
+      uint256[] calldata expectedPrices,
+      bool partialFill
+      ) public payable nonReentrant {
+      require(
+        nftContracts.length == tokenIds.length && tokenIds.length == expectedPrices.length,
+        "Collections, token ids and prices must have the same length"
+      );
+      uint256 totalPrice = 0;
+      for (uint i = 0; i < tokenIds.length; i++) {
+        uint256 itemId = tokenToItemId[nftContracts[i]][tokenIds[i]];
+        if (!isPurchasable(itemId, expectedPrices[i])) {
+          require(partialFill, "Item is no longer available at the expected price");
+          continue;
+        }
+        uint256 price = currentPrice(itemId);
+        if (idToMarketItem[itemId].currency == address(0)) {
+          totalPrice += price;
+          require(msg.value >= totalPrice, "Please submit the asking price in order to complete the purchase");
         }
+        executeSale(itemId, price);
       }
+      if (msg.value > totalPrice) {
+        creditFunds(msg.sender, msg.value - totalPrice);
+      }
+    }
 
-      MarketItem[] memory items = new MarketItem[](itemCount);
-      for (uint i = 0; i < totalItemCount; i++) {
-        if (idToMarketItem[i + 1].owner == msg.sender) {
-          uint currentId = i + 1;
-          MarketItem storage currentItem = idToMarketItem[currentId];
-          items[currentIndex] = currentItem;
-          currentIndex += 1;
-        }
+    function isPurchasable(uint256 itemId, uint256 expectedPrice) private view returns (bool) {
+      MarketItem storage item = idToMarketItem[itemId];
+      return item.owner == address(this)
+        && idToAuction[itemId].endTime == 0
+        && !isExpired(item)
+        && isAllowedBuyer(itemId, msg.sender, new bytes32[](0))
+        && (item.currency == address(0) || allowedCurrencies[item.currency])
+        && currentPrice(itemId) <= expectedPrice;
+    }
+
+    /* Transfers a listed item to the caller and pays out the seller, creator and marketplace */
+    function executeSale(uint256 itemId, uint256 price) private {
+      require(!buyingPaused, "Buying is paused");
+      MarketItem storage item = idToMarketItem[itemId];
+      address seller = item.seller;
+      delete idToDutchAuction[itemId];
+      item.price = price;
+      updateItemHolders(item, msg.sender, address(0));
+      item.sold = true;
+      IERC721(item.nftContract).transferFrom(address(this), msg.sender, item.tokenId);
+      payOutSale(itemId, item.currency, msg.sender, seller, price);
+    }
+
+    /* Allows the seller to change the currency an unsold fixed-price or Dutch auction listing is priced in */
+    function updateItemCurrency(address nftContract, uint256 tokenId, address currency) public {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      MarketItem storage item = idToMarketItem[itemId];
+      require(item.owner == address(this), "Item is not listed");
+      require(item.seller == msg.sender, "Only item seller can perform this operation");
+      require(idToAuction[itemId].endTime == 0, "Auctions can only be priced in the native currency");
+      require(isCurrencyAllowed(currency), "Currency is not allowed");
+
+      item.currency = currency;
+      emit ItemCurrencyChanged(itemId, msg.sender, currency);
+    }
+
+    /* Allows the seller to delist an unsold item and take the token back */
+    function cancelListing(address nftContract, uint256 tokenId) public nonReentrant {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      MarketItem storage item = idToMarketItem[itemId];
+      require(item.owner == address(this), "Item is not listed");
+      require(item.seller == msg.sender, "Only item seller can perform this operation");
+      require(idToAuction[itemId].highestBidder == address(0), "Cannot cancel an auction that has bids");
+      delete idToAuction[itemId];
+      delete idToDutchAuction[itemId];
+
+      updateItemHolders(item, msg.sender, address(0));
+      item.sold = false;
+      IERC721(nftContract).transferFrom(address(this), msg.sender, tokenId);
+      emit MarketItemCancelled(itemId, msg.sender);
+    }
+
+    /* Returns an expired listing to its seller; callable by anyone */
+    function reclaimExpiredItem(address nftContract, uint256 tokenId) public nonReentrant {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      MarketItem storage item = idToMarketItem[itemId];
+      require(item.owner == address(this), "Item is not listed");
+      require(isExpired(item), "Listing has not expired");
+
+      address seller = item.seller;
+      updateItemHolders(item, seller, address(0));
+      item.sold = false;
+      IERC721(nftContract).transferFrom(address(this), seller, tokenId);
+      emit MarketItemExpired(itemId, seller, msg.sender);
+    }
+
+    /* Returns whether a listing has passed its expiry; listings without one never expire */
+    function isExpired(MarketItem storage item) private view returns (bool) {
+      return item.expiresAt != 0 && block.timestamp >= item.expiresAt;
+    }
+
+    /* Allows the seller to change the price of an unsold fixed-price listing */
+    function updateItemPrice(address nftContract, uint256 tokenId, uint256 price) public {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      MarketItem storage item = idToMarketItem[itemId];
+      require(item.owner == address(this), "Item is not listed");
+      require(item.seller == msg.sender, "Only item seller can perform this operation");
+      require(idToAuction[itemId].endTime == 0 && idToDutchAuction[itemId].duration == 0, "Cannot change the price of an auction");
+      require(price > 0, "Price must be at least 1 wei");
+
+      uint256 oldPrice = item.price;
+      item.price = price;
+      emit PriceChanged(itemId, msg.sender, oldPrice, price);
+    }
+
+    /* Returns the price a buyer currently has to pay for a listed item */
+    /* Dutch auction prices decay linearly from the start price to the end price */
+    function getCurrentPrice(address nftContract, uint256 tokenId) public view returns (uint256) {
+      return currentPrice(tokenToItemId[nftContract][tokenId]);
+    }
+
+    function currentPrice(uint256 itemId) private view returns (uint256) {
+      DutchAuction storage dutchAuction = idToDutchAuction[itemId];
+      if (dutchAuction.duration == 0) {
+        return idToMarketItem[itemId].price;
       }
-      return items;
+      uint256 elapsed = block.timestamp - dutchAuction.startTime;
+      if (elapsed >= dutchAuction.duration) {
+        return dutchAuction.endPrice;
+      }
+      uint256 priceDrop = dutchAuction.startPrice - dutchAuction.endPrice;
+      return dutchAuction.startPrice - (priceDrop * elapsed / dutchAuction.duration);
+    }
+
+    /* Withdraws all funds credited to the caller from sales, fees, refunds and outbid bids */
+    function withdraw() public nonReentrant {
+      uint256 amount = credits[msg.sender];
+      require(amount > 0, "No funds to withdraw");
+      credits[msg.sender] = 0;
+      (bool success, ) = payable(msg.sender).call{value: amount}("");
+      require(success, "Withdrawal failed");
+      emit FundsWithdrawn(msg.sender, amount);
+    }
+
+    /* Returns the funds an address can withdraw */
+    function getCredits(address account) public view returns (uint256) {
+      return credits[account];
+    }
+
+    /* Returns all unsold market items open to the public, leaving out expired listings and private sales */
+    function fetchMarketItems() public view returns (MarketItem[] memory) {
+      return itemsIn(activeListings, 0, activeListings.length(), true);
+    }
+
+    /* Returns only items that a user has purchased */
+    function fetchMyNFTs() public view returns (MarketItem[] memory) {
+      return itemsIn(ownerItems[msg.sender], 0, ownerItems[msg.sender].length(), false);
     }
 
     /* Returns only items a user has listed */
     function fetchItemsListed() public view returns (MarketItem[] memory) {
-      uint totalItemCount = _tokenIds.current();
+      return itemsIn(sellerListings[msg.sender], 0, sellerListings[msg.sender].length(), false);
+    }
+
+    /* Returns the private sales reserved for the caller's address */
+    /* Private sales open to a Merkle root of buyers are listed by fetchRootGatedPrivateSales instead */
+    function fetchPrivateSales() public view returns (MarketItem[] memory) {
+      return itemsIn(reservedListings[msg.sender], 0, reservedListings[msg.sender].length(), false);
+    }
+
+    /* Returns every private sale open to a Merkle root of buyers */
+    /* The root only commits to its buyers, so each buyer checks which ones are theirs with isAllowedBuyer */
+    function fetchRootGatedPrivateSales() public view returns (MarketItem[] memory) {
+      return itemsIn(rootGatedListings, 0, rootGatedListings.length(), false);
+    }
+
+    function isPublicListing(uint256 itemId) private view returns (bool) {
+      return !isExpired(idToMarketItem[itemId]) && !isPrivateSale(itemId);
+    }
+
+    /* Returns the number of market items ever created */
+    function getItemCount() public view returns (uint256) {
+      return _itemIds.current();
+    }
+
+    /* Returns the number of entries in the listing index that fetchMarketItemsPage cursors run over */
+    /* It includes expired listings and private sales, which the feed hides, so it bounds the cursor rather than counting feed items */
+    function getListedItemCount() public view returns (uint256) {
+      return activeListings.length();
+    }
+
+    /* Returns a page of unsold market items from at most limit listings starting at the cursor */
+    /* Expired listings and private sales are skipped, so a page can hold fewer items than the limit */
+    /* Start with a cursor of zero and pass back nextCursor until it is zero again */
+    function fetchMarketItemsPage(uint256 cursor, uint256 limit) public view returns (MarketItem[] memory, uint256) {
+      return fetchPage(activeListings, cursor, limit, true);
+    }
+
+    /* Returns a page of the items a user has purchased, paged like fetchMarketItemsPage */
+    function fetchMyNFTsPage(uint256 cursor, uint256 limit) public view returns (MarketItem[] memory, uint256) {
+      return fetchPage(ownerItems[msg.sender], cursor, limit, false);
+    }
+
+    /* Returns a page of the items a user has listed, paged like fetchMarketItemsPage */
+    function fetchItemsListedPage(uint256 cursor, uint256 limit) public view returns (MarketItem[] memory, uint256) {
+      return fetchPage(sellerListings[msg.sender], cursor, limit, false);
+    }
+
+    /* Cursors are positions in the index, which reorders when items leave it */
+    /* A page can therefore skip or repeat an item that changed hands while paging */
+    function fetchPage(
+      EnumerableSet.UintSet storage itemIds,
+      uint256 cursor,
+      uint256 limit,
+      bool publicOnly
+    ) private view returns (MarketItem[] memory, uint256) {
+      require(limit > 0, "Limit must be at least 1");
+      uint totalItemCount = itemIds.length();
+      uint start = cursor < totalItemCount ? cursor : totalItemCount;
+      /* Compared against the remaining count, so a huge limit can't overflow start + limit */
+      uint end = limit < totalItemCount - start ? start + limit : totalItemCount;
+      return (itemsIn(itemIds, start, end, publicOnly), end < totalItemCount ? end : 0);
+    }
+
+    function itemsIn(
+      EnumerableSet.UintSet storage itemIds,
+      uint256 start,
+      uint256 end,
+      bool publicOnly
+    ) private view returns (MarketItem[] memory) {
       uint itemCount = 0;
       uint currentIndex = 0;
 
-      for (uint i = 0; i < totalItemCount; i++) {
-        if (idToMarketItem[i + 1].seller == msg.sender) {
+      for (uint i = start; i < end; i++) {
+        if (!publicOnly || isPublicListing(itemIds.at(i))) {
           itemCount += 1;
         }
       }
 
       MarketItem[] memory items = new MarketItem[](itemCount);
-      for (uint i = 0; i < totalItemCount; i++) {
-        if (idToMarketItem[i + 1].seller == msg.sender) {
-          uint currentId = i + 1;
-          MarketItem storage currentItem = idToMarketItem[currentId];
-          items[currentIndex] = currentItem;
+      for (uint i = start; i < end; i++) {
+        if (!publicOnly || isPublicListing(itemIds.at(i))) {
+          items[currentIndex] = idToMarketItem[itemIds.at(i)];
           currentIndex += 1;
         }
       }
       return items;
     }
+
 }
\ No newline at end of file
@@ -0,0 +1,620 @@
+// SPDX-License-Identifier: MIT
+// pragma solidity ^0.8.4;
+
+import "@openzeppelin/contracts/utils/Counters.sol";
+import "@openzeppelin/contracts/utils/structs/EnumerableSet.sol";
+import "@openzeppelin/contracts/proxy/utils/Initializable.sol";
+import "@openzeppelin/contracts/security/ReentrancyGuard.sol";
+import "@openzeppelin/contracts/access/AccessControl.sol";
+import "@openzeppelin/contracts/token/ERC1155/utils/ERC1155Holder.sol";
+import "@openzeppelin/contracts/utils/cryptography/draft-EIP712.sol";
+import "@openzeppelin/contracts/token/ERC721/IERC721.sol";
+import "@openzeppelin/contracts/token/ERC1155/IERC1155.sol";
+import "@openzeppelin/contracts/interfaces/IERC2981.sol";
+import "@openzeppelin/contracts/utils/introspection/ERC165Checker.sol";
+import "@openzeppelin/contracts/token/ERC20/IERC20.sol";
+import "@openzeppelin/contracts/token/ERC20/utils/SafeERC20.sol";
+
+/* State, events and shared bookkeeping of the marketplace, inherited by NFTMarketplace and NFTMarketplaceExtension */
+/* Both run against the proxy's storage, so neither may declare state of its own */
+/* New state variables go at the end, right before __gap, shrinking the gap by the slots they take */
+abstract contract NFTMarketplaceCore is
+  Initializable,
+  ReentrancyGuard,
+  AccessControl,
+  EIP712,
+  ERC1155Holder
+{
+    using Counters for Counters.Counter;
+    using EnumerableSet for EnumerableSet.UintSet;
+    using SafeERC20 for IERC20;
+    Counters.Counter internal _itemIds;
+    Counters.Counter internal _offerIds;
+
+    uint256 listingPrice;
+    uint256 constant auctionExtensionWindow = 10 minutes;
+    uint96 constant maxMarketplaceFee = 1000;
+    uint96 constant maxRoyaltyFraction = 10000 - maxMarketplaceFee;
+    uint96 marketplaceFee;
+    uint96 defaultRoyaltyFraction;
+    uint256 maxBatchSize;
+    bool mintingPaused;
+    bool listingPaused;
+    bool buyingPaused;
+    bytes32 constant ORDER_TYPEHASH = keccak256(
+      "Order(address maker,bool isOffer,address nftContract,uint256 tokenId,uint256 price,address currency,uint256 expiry,uint256 nonce,uint256 counter)"
+    );
+    bytes32 constant MINT_VOUCHER_TYPEHASH = keccak256(
+      "MintVoucher(address creator,string tokenURI,uint256 price,uint256 nonce)"
+    );
+    bytes32 public constant FEE_MANAGER_ROLE = keccak256("FEE_MANAGER_ROLE");
+    bytes32 public constant PAUSER_ROLE = keccak256("PAUSER_ROLE");
+    bytes32 public constant MODERATOR_ROLE = keccak256("MODERATOR_ROLE");
+    bytes32 public constant UPGRADER_ROLE = keccak256("UPGRADER_ROLE");
+    address payable owner;
+    address pendingOwner;
+    address payable feeRecipient;
+    address tokenContract;
+
+    mapping(uint256 => MarketItem) internal idToMarketItem;
+    mapping(address => mapping(uint256 => uint256)) internal tokenToItemId;
+    mapping(uint256 => Auction) internal idToAuction;
+    mapping(uint256 => DutchAuction) internal idToDutchAuction;
+    mapping(address => uint256) internal credits;
+    mapping(address => bool) internal allowedCurrencies;
+    mapping(uint256 => Offer) internal idToOffer;
+    mapping(address => uint256) internal orderCounters;
+    mapping(address => mapping(uint256 => bool)) internal usedOrderNonces;
+    mapping(address => mapping(uint256 => bool)) internal usedVoucherNonces;
+    EnumerableSet.UintSet internal activeListings;
+    mapping(address => EnumerableSet.UintSet) internal sellerListings;
+    mapping(address => EnumerableSet.UintSet) internal ownerItems;
+    mapping(uint256 => PrivateSale) internal idToPrivateSale;
+    mapping(address => EnumerableSet.UintSet) internal reservedListings;
+    Counters.Counter internal _collectionOfferIds;
+    mapping(uint256 => CollectionOffer) internal idToCollectionOffer;
+    mapping(uint256 => uint256[]) internal bundleTokenIds;
+    EnumerableSet.UintSet internal rootGatedListings;
+    mapping(address => mapping(uint256 => EnumerableSet.UintSet)) internal tokenOffers;
+    mapping(address => EnumerableSet.UintSet) internal collectionOffers;
+    uint256[37] private __gap;
+
+    /* sold is set when the item last changed hands through a sale and cleared when it is listed again */
+    /* MarketItemSold is emitted on every sale, and MarketItemRelisted instead of MarketItemCreated when a sold item is listed again */
+    struct MarketItem {
+      uint256 itemId;
+      address nftContract;
+      uint256 tokenId;
+      address payable seller;
+      address payable owner;
+      uint256 price;
+      bool sold;
+      address currency;
+      uint256 quantity;
+      bool isERC1155;
+      uint256 expiresAt;
+      bool isBundle;
+    }
+
+    event MarketItemCreated (
+      uint256 indexed itemId,
+      address indexed nftContract,
+      uint256 indexed tokenId,
+      address seller,
+      address owner,
+      uint256 price,
+      bool sold
+    );
+
+    event MarketItemRelisted (
+      uint256 indexed itemId,
+      address indexed nftContract,
+      uint256 indexed tokenId,
+      address seller,
+      uint256 price,
+      address currency
+    );
+
+    event MarketItemSold (
+      uint256 indexed itemId,
+      address indexed seller,
+      address indexed buyer,
+      address nftContract,
+      uint256 tokenId,
+      address currency,
+      uint256 price,
+      uint256 marketplaceFee,
+      uint256 royaltyAmount
+    );
+
+    event ListingPriceUpdated (
+      uint256 oldPrice,
+      uint256 newPrice
+    );
+
+    event EditionListingCreated (
+      uint256 indexed itemId,
+      address indexed nftContract,
+      uint256 indexed tokenId,
+      address seller,
+      uint256 quantity,
+      uint256 pricePerUnit
+    );
+
+    event BundleListingCreated (
+      uint256 indexed itemId,
+      address indexed nftContract,
+      address indexed seller,
+      uint256[] tokenIds,
+      uint256 price
+    );
+
+    event EditionsPurchased (
+      uint256 indexed itemId,
+      address indexed buyer,
+      uint256 quantity,
+      uint256 remainingQuantity,
+      uint256 totalPrice
+    );
+
+    event MarketItemCancelled (
+      uint256 indexed itemId,
+      address seller
+    );
+
+    event PrivateSaleCreated (
+      uint256 indexed itemId,
+      address indexed buyer,
+      address seller,
+      bytes32 buyersRoot,
+      uint256 price
+    );
+
+    event MarketItemExpired (
+      uint256 indexed itemId,
+      address seller,
+      address reclaimedBy
+    );
+
+    event PriceChanged (
+      uint256 indexed itemId,
+      address seller,
+      uint256 oldPrice,
+      uint256 newPrice
+    );
+
+    event ItemCurrencyChanged (
+      uint256 indexed itemId,
+      address seller,
+      address currency
+    );
+
+    event CurrencyAllowlistUpdated (
+      address indexed currency,
+      bool allowed
+    );
+
+    event FundsCredited (
+      address indexed account,
+      uint256 amount
+    );
+
+    event FundsWithdrawn (
+      address indexed account,
+      uint256 amount
+    );
+
+    event MarketplaceFeeUpdated (
+      uint96 fee
+    );
+
+    event FeeRecipientUpdated (
+      address indexed feeRecipient
+    );
+
+    event MarketplaceFeePaid (
+      uint256 indexed itemId,
+      address recipient,
+      uint256 amount
+    );
+
+    event RoyaltyPaid (
+      uint256 indexed itemId,
+      address receiver,
+      uint256 amount
+    );
+
+    struct Offer {
+      uint256 offerId;
+      address nftContract;
+      uint256 tokenId;
+      address payable bidder;
+      uint256 amount;
+      uint256 expiry;
+      bool active;
+    }
+
+    /* An offer on any token of a collection, or with traitRoot set, on any token in a Merkle tree of token ids */
+    /* Escrows pricePerToken for each of the quantity tokens still wanted */
+    struct CollectionOffer {
+      uint256 offerId;
+      address nftContract;
+      address payable bidder;
+      uint256 pricePerToken;
+      uint256 quantity;
+      uint256 expiry;
+      bytes32 traitRoot;
+      bool active;
+    }
+
+    event OfferMade (
+      uint256 indexed offerId,
+      address indexed nftContract,
+      uint256 indexed tokenId,
+      address bidder,
+      uint256 amount,
+      uint256 expiry
+    );
+
+    event OfferCancelled (
+      uint256 indexed offerId,
+      address indexed bidder
+    );
+
+    event OfferAccepted (
+      uint256 indexed offerId,
+      uint256 indexed itemId,
+      address seller,
+      address bidder,
+      uint256 amount
+    );
+
+    event CollectionOfferMade (
+      uint256 indexed offerId,
+      address indexed nftContract,
+      address indexed bidder,
+      uint256 pricePerToken,
+      uint256 quantity,
+      uint256 expiry,
+      bytes32 traitRoot
+    );
+
+    event CollectionOfferCancelled (
+      uint256 indexed offerId,
+      address indexed bidder,
+      uint256 refund
+    );
+
+    event CollectionOfferAccepted (
+      uint256 indexed offerId,
+      uint256 indexed itemId,
+      address seller,
+      address bidder,
+      uint256 tokenId,
+      uint256 remainingQuantity
+    );
+
+    /* An off-chain listing (isOffer false) or offer (isOffer true) signed by its maker */
+    struct Order {
+      address maker;
+      bool isOffer;
+      address nftContract;
+      uint256 tokenId;
+      uint256 price;
+      address currency;
+      uint256 expiry;
+      uint256 nonce;
+      uint256 counter;
+    }
+
+    event OrderFulfilled (
+      bytes32 indexed orderHash,
+      address indexed maker,
+      address indexed taker,
+      uint256 itemId,
+      uint256 price,
+      address currency
+    );
+
+    event OrderCancelled (
+      address indexed maker,
+      uint256 nonce
+    );
+
+    event OrderCounterIncremented (
+      address indexed maker,
+      uint256 counter
+    );
+
+    /* A creator's signed permission for a buyer to mint a token at a price */
+    struct MintVoucher {
+      address creator;
+      string tokenURI;
+      uint256 price;
+      uint256 nonce;
+    }
+
+    event VoucherRedeemed (
+      uint256 indexed tokenId,
+      address indexed creator,
+      address indexed buyer,
+      uint256 price,
+      uint256 nonce
+    );
+
+    event VoucherCancelled (
+      address indexed creator,
+      uint256 nonce
+    );
+
+    struct Auction {
+      uint256 itemId;
+      address payable seller;
+      uint256 reservePrice;
+      uint256 minBidIncrement;
+      uint256 endTime;
+      address payable highestBidder;
+      uint256 highestBid;
+    }
+
+    /* A listing only a designated buyer, or any buyer in a Merkle tree of addresses, can purchase */
+    struct PrivateSale {
+      address buyer;
+      bytes32 buyersRoot;
+    }
+
+    struct DutchAuction {
+      uint256 itemId;
+      uint256 startPrice;
+      uint256 endPrice;
+      uint256 startTime;
+      uint256 duration;
+    }
+
+    event AuctionCreated (
+      uint256 indexed itemId,
+      address indexed nftContract,
+      uint256 indexed tokenId,
+      address seller,
+      uint256 reservePrice,
+      uint256 minBidIncrement,
+      uint256 endTime
+    );
+
+    event BidPlaced (
+      uint256 indexed itemId,
+      address indexed bidder,
+      uint256 amount,
+      uint256 endTime
+    );
+
+    event AuctionSettled (
+      uint256 indexed itemId,
+      address winner,
+      uint256 amount
+    );
+
+    event DutchAuctionCreated (
+      uint256 indexed itemId,
+      address indexed nftContract,
+      uint256 indexed tokenId,
+      address seller,
+      uint256 startPrice,
+      uint256 endPrice,
+      uint256 startTime,
+      uint256 duration
+    );
+
+    event PausedUpdated (
+      bool mintingPaused,
+      bool listingPaused,
+      bool buyingPaused
+    );
+
+    event OwnershipTransferStarted (
+      address indexed previousOwner,
+      address indexed newOwner
+    );
+
+    event OwnershipTransferred (
+      address indexed previousOwner,
+      address indexed newOwner
+    );
+
+    /* Returns whether listings can be priced in the given ERC-20 token */
+    function isCurrencyAllowed(address currency) public view returns (bool) {
+      return currency == address(0) || allowedCurrencies[currency];
+    }
+
+    /* Returns the market item of a token, creating an unlisted one the first time the token is seen */
+    function itemIdFor(address nftContract, uint256 tokenId) internal returns (uint256) {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      if (itemId == 0) {
+        _itemIds.increment();
+        itemId = _itemIds.current();
+        tokenToItemId[nftContract][tokenId] = itemId;
+        idToMarketItem[itemId].itemId = itemId;
+        idToMarketItem[itemId].nftContract = nftContract;
+        idToMarketItem[itemId].tokenId = tokenId;
+        idToMarketItem[itemId].quantity = 1;
+      }
+      return itemId;
+    }
+
+    /* Moves a token the caller owns into escrow and marks its market item as listed */
+    function escrowItem(
+      address nftContract,
+      uint256 tokenId,
+      uint256 price,
+      address currency
+    ) internal returns (uint256) {
+      require(IERC721(nftContract).ownerOf(tokenId) == msg.sender, "Only item owner can perform this operation");
+      uint256 itemId = recordListing(nftContract, tokenId, price, currency);
+      IERC721(nftContract).transferFrom(msg.sender, address(this), tokenId);
+      return itemId;
+    }
+
+    /* Marks the market item of a token already held by the marketplace as listed by the caller */
+    /* The listing price is the marketplace's as soon as the item is listed, whatever the price is later changed to */
+    /* Every listing emits one event: MarketItemRelisted if the item's last transfer was a sale, MarketItemCreated otherwise */
+    function recordListing(
+      address nftContract,
+      uint256 tokenId,
+      uint256 price,
+      address currency
+    ) internal returns (uint256) {
+      require(!listingPaused, "Listing is paused");
+      uint256 itemId = itemIdFor(nftContract, tokenId);
+      MarketItem storage item = idToMarketItem[itemId];
+      bool relisted = item.sold;
+      updateItemHolders(item, address(this), msg.sender);
+      item.price = price;
+      item.sold = false;
+      item.currency = currency;
+      item.expiresAt = 0;
+      creditFunds(feeRecipient, listingPrice);
+      if (relisted) {
+        emit MarketItemRelisted(itemId, nftContract, tokenId, msg.sender, price, currency);
+      } else {
+        emit MarketItemCreated(
+          itemId,
+          nftContract,
+          tokenId,
+          msg.sender,
+          address(this),
+          price,
+          false
+        );
+      }
+      return itemId;
+    }
+
+    /* Moves an item to a new owner and seller, keeping the listing and ownership indexes in sync */
+    /* Items owned by the marketplace are listed by their seller; a zero owner is indexed nowhere */
+    /* A listing leaving the marketplace also drops any private sale reservation */
+    function updateItemHolders(MarketItem storage item, address newOwner, address newSeller) internal {
+      if (item.owner == address(this)) {
+        activeListings.remove(item.itemId);
+        sellerListings[item.seller].remove(item.itemId);
+        reservedListings[idToPrivateSale[item.itemId].buyer].remove(item.itemId);
+        rootGatedListings.remove(item.itemId);
+        delete idToPrivateSale[item.itemId];
+      } else {
+        ownerItems[item.owner].remove(item.itemId);
+      }
+
+      item.owner = payable(newOwner);
+      item.seller = payable(newSeller);
+      if (newOwner == address(this)) {
+        activeListings.add(item.itemId);
+        sellerListings[newSeller].add(item.itemId);
+      } else if (newOwner != address(0)) {
+        ownerItems[newOwner].add(item.itemId);
+      }
+    }
+
+    /* Credits native funds to an account for later withdrawal */
+    function creditFunds(address account, uint256 amount) internal {
+      credits[account] += amount;
+      emit FundsCredited(account, amount);
+    }
+
+    /* Splits sale proceeds between the marketplace fee, the creator royalty and the seller */
+    function payOutSale(
+      uint256 itemId,
+      address currency,
+      address buyer,
+      address seller,
+      uint256 price
+    ) internal {
+      uint256 proceeds = price;
+      uint256 feeAmount = price * marketplaceFee / 10000;
+      if (feeAmount > 0) {
+        sendPayment(currency, buyer, feeRecipient, feeAmount);
+        emit MarketplaceFeePaid(itemId, feeRecipient, feeAmount);
+        proceeds -= feeAmount;
+      }
+      uint256 royaltyAmount = payRoyalties(itemId, currency, buyer, seller, price);
+      require(royaltyAmount <= proceeds, "Royalty and marketplace fee must not exceed the sale price");
+      proceeds -= royaltyAmount;
+      sendPayment(currency, buyer, seller, proceeds);
+      emitSale(itemId, currency, buyer, seller, price, feeAmount, royaltyAmount);
+    }
+
+    function emitSale(
+      uint256 itemId,
+      address currency,
+      address buyer,
+      address seller,
+      uint256 price,
+      uint256 feeAmount,
+      uint256 royaltyAmount
+    ) internal {
+      MarketItem storage item = idToMarketItem[itemId];
+      emit MarketItemSold(itemId, seller, buyer, item.nftContract, item.tokenId, currency, price, feeAmount, royaltyAmount);
+    }
+
+    /* Pays the royalties owed on a sale and returns their total */
+    /* A bundle's price is split evenly across its tokens, the first one taking the remainder */
+    /* and each token's royalty is paid on its share to that token's own receiver */
+    function payRoyalties(
+      uint256 itemId,
+      address currency,
+      address buyer,
+      address seller,
+      uint256 price
+    ) internal returns (uint256) {
+      MarketItem storage item = idToMarketItem[itemId];
+      if (!item.isBundle) {
+        return payRoyalty(itemId, item.tokenId, currency, buyer, seller, price);
+      }
+      uint256[] storage tokenIds = bundleTokenIds[itemId];
+      uint256 share = price / tokenIds.length;
+      uint256 royaltyAmount = payRoyalty(itemId, tokenIds[0], currency, buyer, seller, price - share * (tokenIds.length - 1));
+      for (uint i = 1; i < tokenIds.length; i++) {
+        royaltyAmount += payRoyalty(itemId, tokenIds[i], currency, buyer, seller, share);
+      }
+      return royaltyAmount;
+    }
+
+    /* Sellers owe no royalty to themselves */
+    function payRoyalty(
+      uint256 itemId,
+      uint256 tokenId,
+      address currency,
+      address buyer,
+      address seller,
+      uint256 price
+    ) internal returns (uint256) {
+      (address receiver, uint256 amount) = royaltyFor(idToMarketItem[itemId].nftContract, tokenId, price);
+      if (receiver == seller || amount == 0) {
+        return 0;
+      }
+      sendPayment(currency, buyer, receiver, amount);
+      emit RoyaltyPaid(itemId, receiver, amount);
+      return amount;
+    }
+
+    /* Returns the ERC-2981 royalty owed on a sale, or none if the collection does not implement it */
+    function royaltyFor(address nftContract, uint256 tokenId, uint256 price) internal view returns (address, uint256) {
+      if (!ERC165Checker.supportsInterface(nftContract, type(IERC2981).interfaceId)) {
+        return (address(0), 0);
+      }
+      (address receiver, uint256 amount) = IERC2981(nftContract).royaltyInfo(tokenId, price);
+      require(amount <= price, "Royalty must not exceed the sale price");
+      return (receiver, amount);
+    }
+
+    /* Credits native currency held by the contract for withdrawal, or pays ERC-20 tokens directly from the buyer */
+    function sendPayment(address currency, address buyer, address to, uint256 amount) internal {
+      if (currency == address(0)) {
+        creditFunds(to, amount);
+      } else {
+        IERC20(currency).safeTransferFrom(buyer, to, amount);
+      }
+    }
+
+    function supportsInterface(bytes4 interfaceId) public view virtual override(AccessControl, ERC1155Receiver) returns (bool) {
+      return super.supportsInterface(interfaceId);
+    }
+}
\ No newline at end of file
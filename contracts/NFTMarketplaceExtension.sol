@@ -0,0 +1,593 @@
+// SPDX-License-Identifier: MIT
+// pragma solidity ^0.8.4;
+
+import "@openzeppelin/contracts/utils/cryptography/SignatureChecker.sol";
+import "@openzeppelin/contracts/utils/cryptography/MerkleProof.sol";
+
+import "./NFTMarketplaceCore.sol";
+
+/* Editions, bundles, auctions, offers and signed orders of the marketplace */
+/* NFTMarketplace delegates every call it does not implement itself here, so these run in the proxy's storage */
+/* Calling this contract directly only touches its own, unused storage */
+contract NFTMarketplaceExtension is NFTMarketplaceCore {
+    using Counters for Counters.Counter;
+    using EnumerableSet for EnumerableSet.UintSet;
+
+    constructor() EIP712("NFTMarketplace", "1") initializer {}
+
+    /* Lists a quantity of editions of an ERC-1155 token at a price per edition */
+    /* Every edition listing is its own market item, as several sellers can list the same token id */
+    function createEditionListing(
+      address nftContract,
+      uint256 tokenId,
+      uint256 quantity,
+      uint256 pricePerUnit
+      ) public payable nonReentrant returns (uint) {
+      require(!listingPaused, "Listing is paused");
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(quantity > 0, "Quantity must be at least 1");
+      require(pricePerUnit > 0, "Price must be at least 1 wei");
+      releaseEditionPurchases(nftContract, tokenId, quantity);
+
+      _itemIds.increment();
+      uint256 itemId = _itemIds.current();
+      MarketItem storage item = idToMarketItem[itemId];
+      item.itemId = itemId;
+      item.nftContract = nftContract;
+      item.tokenId = tokenId;
+      updateItemHolders(item, address(this), msg.sender);
+      item.price = pricePerUnit;
+      item.quantity = quantity;
+      item.isERC1155 = true;
+      creditFunds(feeRecipient, msg.value);
+
+      IERC1155(nftContract).safeTransferFrom(msg.sender, address(this), tokenId, quantity, "");
+      emit EditionListingCreated(itemId, nftContract, tokenId, msg.sender, quantity, pricePerUnit);
+      return itemId;
+    }
+
+    /* Draws listed editions down from the caller's records of the token, so fetchMyNFTs stops showing them */
+    /* Walks the caller's items backwards, as removing one moves the last item into its place */
+    function releaseEditionPurchases(address nftContract, uint256 tokenId, uint256 quantity) private {
+      EnumerableSet.UintSet storage owned = ownerItems[msg.sender];
+      for (uint i = owned.length(); i > 0 && quantity > 0; i--) {
+        MarketItem storage purchase = idToMarketItem[owned.at(i - 1)];
+        if (!purchase.isERC1155 || purchase.nftContract != nftContract || purchase.tokenId != tokenId) {
+          continue;
+        }
+        uint256 released = purchase.quantity < quantity ? purchase.quantity : quantity;
+        purchase.quantity -= released;
+        quantity -= released;
+        if (purchase.quantity == 0) {
+          updateItemHolders(purchase, address(0), address(0));
+        }
+      }
+    }
+
+    /* Buys some of the editions remaining in an ERC-1155 listing */
+    /* The purchased editions are recorded as a new market item owned by the buyer */
+    function buyEditions(uint256 itemId, uint256 quantity) public payable nonReentrant returns (uint) {
+      require(!buyingPaused, "Buying is paused");
+      MarketItem storage listing = idToMarketItem[itemId];
+      require(listing.isERC1155 && listing.owner == address(this), "Item is not listed");
+      require(quantity > 0 && quantity <= listing.quantity, "Not enough editions available");
+      uint256 totalPrice = listing.price * quantity;
+      require(msg.value == totalPrice, "Please submit the asking price in order to complete the purchase");
+
+      address seller = listing.seller;
+      listing.quantity -= quantity;
+      if (listing.quantity == 0) {
+        updateItemHolders(listing, address(0), address(0));
+        listing.sold = true;
+      }
+
+      uint256 purchaseId = recordEditionPurchase(listing, quantity);
+      IERC1155(listing.nftContract).safeTransferFrom(address(this), msg.sender, listing.tokenId, quantity, "");
+      payOutSale(purchaseId, address(0), msg.sender, seller, totalPrice);
+      emit EditionsPurchased(itemId, msg.sender, quantity, listing.quantity, totalPrice);
+      return purchaseId;
+    }
+
+    function recordEditionPurchase(MarketItem storage listing, uint256 quantity) private returns (uint256) {
+      _itemIds.increment();
+      uint256 purchaseId = _itemIds.current();
+      MarketItem storage purchase = idToMarketItem[purchaseId];
+      purchase.itemId = purchaseId;
+      purchase.nftContract = listing.nftContract;
+      purchase.tokenId = listing.tokenId;
+      updateItemHolders(purchase, msg.sender, address(0));
+      purchase.price = listing.price;
+      purchase.sold = true;
+      purchase.quantity = quantity;
+      purchase.isERC1155 = true;
+      return purchaseId;
+    }
+
+    /* Allows the seller to delist the editions remaining in an ERC-1155 listing */
+    function cancelEditionListing(uint256 itemId) public nonReentrant {
+      MarketItem storage item = idToMarketItem[itemId];
+      require(item.isERC1155 && item.owner == address(this), "Item is not listed");
+      require(item.seller == msg.sender, "Only item seller can perform this operation");
+
+      updateItemHolders(item, msg.sender, address(0));
+      IERC1155(item.nftContract).safeTransferFrom(address(this), msg.sender, item.tokenId, item.quantity, "");
+      emit MarketItemCancelled(itemId, msg.sender);
+    }
+
+    /* Lists several tokens of one collection as a bundle that can only be bought as a whole */
+    /* The bundle is a single market item whose tokenId is the first token; each token's royalty is paid on its share of the price */
+    function createBundleListing(
+      address nftContract,
+      uint256[] calldata tokenIds,
+      uint256 price
+      ) public payable nonReentrant returns (uint) {
+      require(!listingPaused, "Listing is paused");
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(price > 0, "Price must be at least 1 wei");
+      require(tokenIds.length > 1, "Bundle must contain at least two tokens");
+      require(tokenIds.length <= maxBatchSize, "Batch exceeds the maximum batch size");
+
+      _itemIds.increment();
+      uint256 itemId = _itemIds.current();
+      MarketItem storage item = idToMarketItem[itemId];
+      item.itemId = itemId;
+      item.nftContract = nftContract;
+      item.tokenId = tokenIds[0];
+      updateItemHolders(item, address(this), msg.sender);
+      item.price = price;
+      item.quantity = tokenIds.length;
+      item.isBundle = true;
+      bundleTokenIds[itemId] = tokenIds;
+      creditFunds(feeRecipient, msg.value);
+
+      for (uint i = 0; i < tokenIds.length; i++) {
+        require(IERC721(nftContract).ownerOf(tokenIds[i]) == msg.sender, "Only item owner can perform this operation");
+        uint256 tokenItemId = tokenToItemId[nftContract][tokenIds[i]];
+        if (tokenItemId != 0) {
+          updateItemHolders(idToMarketItem[tokenItemId], address(0), address(0));
+        }
+        IERC721(nftContract).transferFrom(msg.sender, address(this), tokenIds[i]);
+      }
+      emit BundleListingCreated(itemId, nftContract, msg.sender, tokenIds, price);
+      return itemId;
+    }
+
+    /* Buys every token of a bundle at the bundle price */
+    function buyBundle(uint256 itemId) public payable nonReentrant {
+      require(!buyingPaused, "Buying is paused");
+      MarketItem storage item = idToMarketItem[itemId];
+      require(item.isBundle && item.owner == address(this), "Item is not listed");
+      require(msg.value == item.price, "Please submit the asking price in order to complete the purchase");
+
+      address seller = item.seller;
+      updateItemHolders(item, address(0), address(0));
+      item.sold = true;
+      transferBundle(itemId, msg.sender, true);
+      payOutSale(itemId, address(0), msg.sender, seller, item.price);
+    }
+
+    /* Allows the seller to delist a bundle and take its tokens back */
+    function cancelBundleListing(uint256 itemId) public nonReentrant {
+      MarketItem storage item = idToMarketItem[itemId];
+      require(item.isBundle && item.owner == address(this), "Item is not listed");
+      require(item.seller == msg.sender, "Only item seller can perform this operation");
+
+      updateItemHolders(item, address(0), address(0));
+      transferBundle(itemId, msg.sender, false);
+      emit MarketItemCancelled(itemId, msg.sender);
+    }
+
+    /* Returns the token ids grouped in a bundle */
+    function fetchBundleTokenIds(uint256 itemId) public view returns (uint256[] memory) {
+      return bundleTokenIds[itemId];
+    }
+
+    /* Hands every token of a bundle to the recipient, each under its own market item */
+    /* The bundle item itself is then indexed nowhere, so the tokens show up once in fetchMyNFTs */
+    function transferBundle(uint256 itemId, address to, bool sold) private {
+      address nftContract = idToMarketItem[itemId].nftContract;
+      uint256[] storage tokenIds = bundleTokenIds[itemId];
+      for (uint i = 0; i < tokenIds.length; i++) {
+        MarketItem storage item = idToMarketItem[itemIdFor(nftContract, tokenIds[i])];
+        updateItemHolders(item, to, address(0));
+        item.sold = sold;
+        IERC721(nftContract).transferFrom(address(this), to, tokenIds[i]);
+      }
+    }
+
+    /* Lists a token the caller owns with a price declining from startPrice to endPrice over duration */
+    function createDutchAuction(
+      address nftContract,
+      uint256 tokenId,
+      uint256 startPrice,
+      uint256 endPrice,
+      uint256 duration
+      ) public payable nonReentrant {
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(endPrice > 0, "Price must be at least 1 wei");
+      require(startPrice > endPrice, "Start price must be greater than end price");
+      require(duration > 0, "Auction duration must be greater than zero");
+
+      uint256 itemId = escrowItem(nftContract, tokenId, startPrice, address(0));
+      idToDutchAuction[itemId] = DutchAuction(
+        itemId,
+        startPrice,
+        endPrice,
+        block.timestamp,
+        duration
+      );
+      emit DutchAuctionCreated(
+        itemId,
+        nftContract,
+        tokenId,
+        msg.sender,
+        startPrice,
+        endPrice,
+        block.timestamp,
+        duration
+      );
+    }
+
+    /* Returns the Dutch auction parameters of a token */
+    function fetchDutchAuction(address nftContract, uint256 tokenId) public view returns (DutchAuction memory) {
+      return idToDutchAuction[tokenToItemId[nftContract][tokenId]];
+    }
+
+    /* Lists a token the caller owns as a timed English auction */
+    function createAuction(
+      address nftContract,
+      uint256 tokenId,
+      uint256 reservePrice,
+      uint256 minBidIncrement,
+      uint256 duration
+      ) public payable nonReentrant {
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+      require(reservePrice > 0, "Reserve price must be at least 1 wei");
+      require(minBidIncrement > 0, "Bid increment must be at least 1 wei");
+      require(duration > 0, "Auction duration must be greater than zero");
+
+      uint256 itemId = escrowItem(nftContract, tokenId, reservePrice, address(0));
+      uint256 endTime = block.timestamp + duration;
+      idToAuction[itemId] = Auction(
+        itemId,
+        payable(msg.sender),
+        reservePrice,
+        minBidIncrement,
+        endTime,
+        payable(address(0)),
+        0
+      );
+      emit AuctionCreated(
+        itemId,
+        nftContract,
+        tokenId,
+        msg.sender,
+        reservePrice,
+        minBidIncrement,
+        endTime
+      );
+    }
+
+    /* Places a bid on a running auction, crediting the outbid bidder for withdrawal */
+    /* Bids in the last minutes of an auction extend it to prevent sniping */
+    function placeBid(address nftContract, uint256 tokenId) public payable {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      require(!buyingPaused, "Buying is paused");
+      Auction storage auction = idToAuction[itemId];
+      require(auction.endTime != 0, "Item is not listed as an auction");
+      require(block.timestamp < auction.endTime, "Auction has already ended");
+      require(msg.sender != auction.seller, "Seller cannot bid on their own auction");
+      if (auction.highestBidder == address(0)) {
+        require(msg.value >= auction.reservePrice, "Bid must be at least the reserve price");
+      } else {
+        require(msg.value >= auction.highestBid + auction.minBidIncrement, "Bid must exceed the highest bid by the minimum increment");
+        creditFunds(auction.highestBidder, auction.highestBid);
+      }
+
+      auction.highestBidder = payable(msg.sender);
+      auction.highestBid = msg.value;
+      if (auction.endTime - block.timestamp < auctionExtensionWindow) {
+        auction.endTime = block.timestamp + auctionExtensionWindow;
+      }
+      emit BidPlaced(itemId, msg.sender, msg.value, auction.endTime);
+    }
+
+    /* Settles an ended auction; callable by anyone */
+    /* Transfers the token to the highest bidder, or back to the seller if there were no bids */
+    function settleAuction(address nftContract, uint256 tokenId) public nonReentrant {
+      uint256 itemId = tokenToItemId[nftContract][tokenId];
+      Auction memory auction = idToAuction[itemId];
+      require(auction.endTime != 0, "Item is not listed as an auction");
+      require(block.timestamp >= auction.endTime, "Auction has not ended yet");
+      delete idToAuction[itemId];
+
+      address payable recipient = auction.highestBidder == address(0) ? auction.seller : auction.highestBidder;
+      updateItemHolders(idToMarketItem[itemId], recipient, address(0));
+      idToMarketItem[itemId].sold = auction.highestBidder != address(0);
+      IERC721(nftContract).transferFrom(address(this), recipient, tokenId);
+      if (auction.highestBid > 0) {
+        payOutSale(itemId, address(0), auction.highestBidder, auction.seller, auction.highestBid);
+      }
+      emit AuctionSettled(itemId, auction.highestBidder, auction.highestBid);
+    }
+
+    /* Returns the auction state of a token */
+    function fetchAuction(address nftContract, uint256 tokenId) public view returns (Auction memory) {
+      return idToAuction[tokenToItemId[nftContract][tokenId]];
+    }
+
+    /* Makes an offer on any token, escrowing the offered amount until it is accepted or cancelled */
+    function makeOffer(address nftContract, uint256 tokenId, uint256 expiry) public payable returns (uint) {
+      require(!buyingPaused, "Buying is paused");
+      require(msg.value > 0, "Offer must be at least 1 wei");
+      require(expiry > block.timestamp, "Offer expiry must be in the future");
+      require(IERC721(nftContract).ownerOf(tokenId) != msg.sender, "Cannot make an offer on your own token");
+
+      _offerIds.increment();
+      uint256 offerId = _offerIds.current();
+      idToOffer[offerId] = Offer(
+        offerId,
+        nftContract,
+        tokenId,
+        payable(msg.sender),
+        msg.value,
+        expiry,
+        true
+      );
+      tokenOffers[nftContract][tokenId].add(offerId);
+      emit OfferMade(offerId, nftContract, tokenId, msg.sender, msg.value, expiry);
+      return offerId;
+    }
+
+    /* Cancels an offer and credits the escrowed amount back to the bidder */
+    /* Expired offers have to be cancelled for the bidder to get their funds back */
+    function cancelOffer(uint256 offerId) public {
+      Offer storage offer = idToOffer[offerId];
+      require(offer.active, "Offer is not active");
+      require(offer.bidder == msg.sender, "Only the bidder can cancel an offer");
+
+      offer.active = false;
+      tokenOffers[offer.nftContract][offer.tokenId].remove(offerId);
+      creditFunds(offer.bidder, offer.amount);
+      emit OfferCancelled(offerId, msg.sender);
+    }
+
+    /* Allows the owner of a token that is not listed to accept an offer on it */
+    /* The seller pays the listing price, as when listing the token */
+    function acceptOffer(uint256 offerId) public payable nonReentrant {
+      require(!buyingPaused, "Buying is paused");
+      Offer storage offer = idToOffer[offerId];
+      require(offer.active, "Offer is not active");
+      require(offer.expiry > block.timestamp, "Offer has expired");
+      require(IERC721(offer.nftContract).ownerOf(offer.tokenId) == msg.sender, "Only token owner can perform this operation");
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+
+      offer.active = false;
+      tokenOffers[offer.nftContract][offer.tokenId].remove(offerId);
+      uint256 itemId = itemIdFor(offer.nftContract, offer.tokenId);
+      updateItemHolders(idToMarketItem[itemId], offer.bidder, address(0));
+      idToMarketItem[itemId].price = offer.amount;
+      idToMarketItem[itemId].sold = true;
+      IERC721(offer.nftContract).transferFrom(msg.sender, offer.bidder, offer.tokenId);
+      creditFunds(feeRecipient, listingPrice);
+      payOutSale(itemId, address(0), offer.bidder, msg.sender, offer.amount);
+      emit OfferAccepted(offerId, itemId, msg.sender, offer.bidder, offer.amount);
+    }
+
+    /* Returns the active, unexpired offers on a token */
+    /* Only active offers are indexed, so this costs as much as the offers still open on the token */
+    function fetchOffers(address nftContract, uint256 tokenId) public view returns (Offer[] memory) {
+      EnumerableSet.UintSet storage offerIds = tokenOffers[nftContract][tokenId];
+      uint offerCount = 0;
+      uint currentIndex = 0;
+
+      for (uint i = 0; i < offerIds.length(); i++) {
+        if (idToOffer[offerIds.at(i)].expiry > block.timestamp) {
+          offerCount += 1;
+        }
+      }
+
+      Offer[] memory offers = new Offer[](offerCount);
+      for (uint i = 0; i < offerIds.length(); i++) {
+        if (idToOffer[offerIds.at(i)].expiry > block.timestamp) {
+          offers[currentIndex] = idToOffer[offerIds.at(i)];
+          currentIndex += 1;
+        }
+      }
+      return offers;
+    }
+
+    /* Offers pricePerToken for each of quantity tokens of a collection, escrowing the total */
+    function makeCollectionOffer(
+      address nftContract,
+      uint256 pricePerToken,
+      uint256 quantity,
+      uint256 expiry
+      ) public payable returns (uint) {
+      return createCollectionOffer(nftContract, pricePerToken, quantity, expiry, bytes32(0));
+    }
+
+    /* Offers pricePerToken for each of quantity tokens with a trait, escrowing the total */
+    /* traitRoot is the root of a Merkle tree whose leaves are keccak256(abi.encodePacked(tokenId)) of the qualifying tokens */
+    function makeTraitOffer(
+      address nftContract,
+      uint256 pricePerToken,
+      uint256 quantity,
+      uint256 expiry,
+      bytes32 traitRoot
+      ) public payable returns (uint) {
+      require(traitRoot != bytes32(0), "Trait offers need a Merkle root of qualifying tokens");
+      return createCollectionOffer(nftContract, pricePerToken, quantity, expiry, traitRoot);
+    }
+
+    function createCollectionOffer(
+      address nftContract,
+      uint256 pricePerToken,
+      uint256 quantity,
+      uint256 expiry,
+      bytes32 traitRoot
+    ) private returns (uint) {
+      require(!buyingPaused, "Buying is paused");
+      require(pricePerToken > 0, "Offer must be at least 1 wei");
+      require(quantity > 0, "Quantity must be at least 1");
+      require(expiry > block.timestamp, "Offer expiry must be in the future");
+      require(msg.value == pricePerToken * quantity, "Please submit the price per token times the quantity");
+
+      _collectionOfferIds.increment();
+      uint256 offerId = _collectionOfferIds.current();
+      idToCollectionOffer[offerId] = CollectionOffer(
+        offerId,
+        nftContract,
+        payable(msg.sender),
+        pricePerToken,
+        quantity,
+        expiry,
+        traitRoot,
+        true
+      );
+      collectionOffers[nftContract].add(offerId);
+      emit CollectionOfferMade(offerId, nftContract, msg.sender, pricePerToken, quantity, expiry, traitRoot);
+      return offerId;
+    }
+
+    /* Cancels a collection or trait offer and credits the escrow for the tokens still wanted back to the bidder */
+    function cancelCollectionOffer(uint256 offerId) public {
+      CollectionOffer storage offer = idToCollectionOffer[offerId];
+      require(offer.active, "Offer is not active");
+      require(offer.bidder == msg.sender, "Only the bidder can cancel an offer");
+
+      uint256 refund = offer.pricePerToken * offer.quantity;
+      offer.active = false;
+      offer.quantity = 0;
+      collectionOffers[offer.nftContract].remove(offerId);
+      creditFunds(offer.bidder, refund);
+      emit CollectionOfferCancelled(offerId, msg.sender, refund);
+    }
+
+    /* Sells a token the caller holds into a collection or trait offer */
+    /* Trait offers need a Merkle proof that the token is in the trait root; collection offers ignore the proof */
+    /* The seller pays the listing price, as when accepting a single-token offer */
+    function acceptCollectionOffer(
+      uint256 offerId,
+      uint256 tokenId,
+      bytes32[] calldata proof
+      ) public payable nonReentrant {
+      require(!buyingPaused, "Buying is paused");
+      CollectionOffer storage offer = idToCollectionOffer[offerId];
+      require(offer.active, "Offer is not active");
+      require(offer.expiry > block.timestamp, "Offer has expired");
+      require(IERC721(offer.nftContract).ownerOf(tokenId) == msg.sender, "Only token owner can perform this operation");
+      require(
+        offer.traitRoot == bytes32(0) || MerkleProof.verify(proof, offer.traitRoot, keccak256(abi.encodePacked(tokenId))),
+        "Token does not have the offered trait"
+      );
+      require(msg.value == listingPrice, "Price must be equal to listing price");
+
+      offer.quantity -= 1;
+      if (offer.quantity == 0) {
+        offer.active = false;
+        collectionOffers[offer.nftContract].remove(offerId);
+      }
+      uint256 itemId = itemIdFor(offer.nftContract, tokenId);
+      updateItemHolders(idToMarketItem[itemId], offer.bidder, address(0));
+      idToMarketItem[itemId].price = offer.pricePerToken;
+      idToMarketItem[itemId].sold = true;
+      IERC721(offer.nftContract).transferFrom(msg.sender, offer.bidder, tokenId);
+      creditFunds(feeRecipient, listingPrice);
+      payOutSale(itemId, address(0), offer.bidder, msg.sender, offer.pricePerToken);
+      emit CollectionOfferAccepted(offerId, itemId, msg.sender, offer.bidder, tokenId, offer.quantity);
+    }
+
+    /* Returns the active, unexpired collection and trait offers on a collection */
+    /* Only active offers are indexed, so this costs as much as the offers still open on the collection */
+    function fetchCollectionOffers(address nftContract) public view returns (CollectionOffer[] memory) {
+      EnumerableSet.UintSet storage offerIds = collectionOffers[nftContract];
+      uint offerCount = 0;
+      uint currentIndex = 0;
+
+      for (uint i = 0; i < offerIds.length(); i++) {
+        if (idToCollectionOffer[offerIds.at(i)].expiry > block.timestamp) {
+          offerCount += 1;
+        }
+      }
+
+      CollectionOffer[] memory offers = new CollectionOffer[](offerCount);
+      for (uint i = 0; i < offerIds.length(); i++) {
+        if (idToCollectionOffer[offerIds.at(i)].expiry > block.timestamp) {
+          offers[currentIndex] = idToCollectionOffer[offerIds.at(i)];
+          currentIndex += 1;
+        }
+      }
+      return offers;
+    }
+
+    /* Returns the EIP-712 digest a maker signs for an order */
+    function hashOrder(Order calldata order) public view returns (bytes32) {
+      return _hashTypedDataV4(keccak256(abi.encode(
+        ORDER_TYPEHASH,
+        order.maker,
+        order.isOffer,
+        order.nftContract,
+        order.tokenId,
+        order.price,
+        order.currency,
+        order.expiry,
+        order.nonce,
+        order.counter
+      )));
+    }
+
+    /* Fulfils a signed order: buys a signed listing, or sells the caller's token into a signed offer */
+    /* Listings keep the token with the seller until fulfilment; offers must be priced in an ERC-20 currency */
+    function fulfillOrder(Order calldata order, bytes calldata signature) public payable nonReentrant {
+      require(!buyingPaused, "Buying is paused");
+      bytes32 orderHash = hashOrder(order);
+      require(order.expiry > block.timestamp, "Order has expired");
+      require(order.counter == orderCounters[order.maker], "Order has been cancelled");
+      require(!usedOrderNonces[order.maker][order.nonce], "Order has already been used or cancelled");
+      require(SignatureChecker.isValidSignatureNow(order.maker, orderHash, signature), "Invalid order signature");
+      require(order.maker != msg.sender, "Cannot fulfil your own order");
+      require(isCurrencyAllowed(order.currency), "Currency is not allowed");
+
+      address seller = order.isOffer ? msg.sender : order.maker;
+      address buyer = order.isOffer ? order.maker : msg.sender;
+      require(IERC721(order.nftContract).ownerOf(order.tokenId) == seller, "Seller does not own the token");
+      if (order.currency == address(0)) {
+        require(!order.isOffer, "Offers must be priced in an ERC-20 currency");
+        require(msg.value == order.price, "Please submit the asking price in order to complete the purchase");
+      } else {
+        require(msg.value == 0, "Item is priced in an ERC-20 currency");
+      }
+
+      usedOrderNonces[order.maker][order.nonce] = true;
+      uint256 itemId = itemIdFor(order.nftContract, order.tokenId);
+      updateItemHolders(idToMarketItem[itemId], buyer, address(0));
+      idToMarketItem[itemId].price = order.price;
+      idToMarketItem[itemId].sold = true;
+      IERC721(order.nftContract).transferFrom(seller, buyer, order.tokenId);
+      payOutSale(itemId, order.currency, buyer, seller, order.price);
+      emit OrderFulfilled(orderHash, order.maker, msg.sender, itemId, order.price, order.currency);
+    }
+
+    /* Cancels a single signed order of the caller by its nonce */
+    function cancelOrder(uint256 nonce) public {
+      usedOrderNonces[msg.sender][nonce] = true;
+      emit OrderCancelled(msg.sender, nonce);
+    }
+
+    /* Cancels every outstanding signed order of the caller */
+    function incrementOrderCounter() public {
+      orderCounters[msg.sender] += 1;
+      emit OrderCounterIncremented(msg.sender, orderCounters[msg.sender]);
+    }
+
+    /* Returns the counter new orders of a maker must be signed with */
+    function getOrderCounter(address maker) public view returns (uint256) {
+      return orderCounters[maker];
+    }
+
+    /* Returns whether a maker's order nonce has been used or cancelled */
+    function isOrderNonceUsed(address maker, uint256 nonce) public view returns (bool) {
+      return usedOrderNonces[maker][nonce];
+    }
+}
\ No newline at end of file
@@ -0,0 +1,9 @@
+// SPDX-License-Identifier: MIT
+// pragma solidity ^0.8.4;
+
+import "@openzeppelin/contracts/proxy/ERC1967/ERC1967Proxy.sol";
+
+/* ERC-1967 proxy the marketplace is deployed behind; upgrades go through NFTMarketplace.upgradeTo */
+contract NFTMarketplaceProxy is ERC1967Proxy {
+    constructor(address implementation, bytes memory initData) ERC1967Proxy(implementation, initData) {}
+}
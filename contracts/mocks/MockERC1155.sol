@@ -0,0 +1,13 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.4;
+
+import "@openzeppelin/contracts/token/ERC1155/ERC1155.sol";
+
+/* Freely mintable ERC-1155 collection used for edition listings in tests */
+contract MockERC1155 is ERC1155 {
+    constructor() ERC1155("https://www.mytokenlocation.com/{id}.json") {}
+
+    function mint(address to, uint256 tokenId, uint256 amount) public {
+      _mint(to, tokenId, amount, "");
+    }
+}
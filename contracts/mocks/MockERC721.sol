@@ -0,0 +1,13 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.4;
+
+import "@openzeppelin/contracts/token/ERC721/ERC721.sol";
+
+/* Freely mintable ERC-721 collection used as an external collection in tests */
+contract MockERC721 is ERC721 {
+    constructor(string memory name_, string memory symbol_) ERC721(name_, symbol_) {}
+
+    function mint(address to, uint256 tokenId) public {
+      _mint(to, tokenId);
+    }
+}
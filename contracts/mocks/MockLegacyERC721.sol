@@ -0,0 +1,13 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.4;
+
+import "./MockERC721.sol";
+
+/* ERC-721 collection predating ERC-165, whose supportsInterface reverts, used in tests */
+contract MockLegacyERC721 is MockERC721 {
+    constructor() MockERC721("Legacy Collection", "LEGACY") {}
+
+    function supportsInterface(bytes4) public pure override returns (bool) {
+      revert("ERC-165 is not supported");
+    }
+}
@@ -0,0 +1,13 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.4;
+
+import "../NFTMarket.sol";
+
+/* Next version of the marketplace used to check that state survives an upgrade in tests */
+contract NFTMarketplaceV2 is NFTMarketplace {
+    constructor(address _extension) NFTMarketplace(_extension) {}
+
+    function version() public pure returns (string memory) {
+      return "2";
+    }
+}
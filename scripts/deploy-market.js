@@ -0,0 +1,39 @@
+// Deploys the marketplace behind a UUPS proxy and its own NFT collection.
+//
+// npx hardhat run scripts/deploy-market.js --network <network>
+const hre = require("hardhat");
+
+async function main() {
+  const NFTMarketplaceExtension = await hre.ethers.getContractFactory("NFTMarketplaceExtension");
+  const extension = await NFTMarketplaceExtension.deploy();
+  await extension.deployed();
+  console.log("NFTMarketplaceExtension deployed to:", extension.address);
+
+  const NFTMarketplace = await hre.ethers.getContractFactory("NFTMarketplace");
+  const implementation = await NFTMarketplace.deploy(extension.address);
+  await implementation.deployed();
+  console.log("NFTMarketplace implementation deployed to:", implementation.address);
+
+  const NFTMarketplaceProxy = await hre.ethers.getContractFactory("NFTMarketplaceProxy");
+  const proxy = await NFTMarketplaceProxy.deploy(
+    implementation.address,
+    NFTMarketplace.interface.encodeFunctionData("initialize")
+  );
+  await proxy.deployed();
+  const market = NFTMarketplace.attach(proxy.address);
+  console.log("NFTMarketplace proxy deployed to:", market.address);
+
+  const NFT = await hre.ethers.getContractFactory("NFT");
+  const nft = await NFT.deploy(market.address);
+  await nft.deployed();
+  console.log("NFT deployed to:", nft.address);
+
+  await (await market.updateTokenContract(nft.address)).wait();
+}
+
+main()
+  .then(() => process.exit(0))
+  .catch((error) => {
+    console.error(error);
+    process.exit(1);
+  });
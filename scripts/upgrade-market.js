@@ -0,0 +1,31 @@
+// Upgrades the marketplace proxy to the current NFTMarketplace implementation.
+// The proxy address, and therefore every escrowed token and listing, stays the same.
+//
+// MARKET_ADDRESS=<proxy> npx hardhat run scripts/upgrade-market.js --network <network>
+const hre = require("hardhat");
+
+async function main() {
+  const proxyAddress = process.env.MARKET_ADDRESS;
+  if (!proxyAddress) {
+    throw new Error("Set MARKET_ADDRESS to the marketplace proxy address");
+  }
+
+  const NFTMarketplaceExtension = await hre.ethers.getContractFactory("NFTMarketplaceExtension");
+  const extension = await NFTMarketplaceExtension.deploy();
+  await extension.deployed();
+
+  const NFTMarketplace = await hre.ethers.getContractFactory("NFTMarketplace");
+  const implementation = await NFTMarketplace.deploy(extension.address);
+  await implementation.deployed();
+
+  const market = NFTMarketplace.attach(proxyAddress);
+  await (await market.upgradeTo(implementation.address)).wait();
+  console.log("NFTMarketplace proxy", market.address, "now points to:", implementation.address, "with extension:", extension.address);
+}
+
+main()
+  .then(() => process.exit(0))
+  .catch((error) => {
+    console.error(error);
+    process.exit(1);
+  });
@@ -0,0 +1,1263 @@
+const { expect } = require("chai");
+const { ethers } = require("hardhat");
+
+async function increaseTime(seconds) {
+  await ethers.provider.send("evm_increaseTime", [seconds]);
+  await ethers.provider.send("evm_mine", []);
+}
+
+async function deployImplementation(name) {
+  const NFTMarketplaceExtension = await ethers.getContractFactory("NFTMarketplaceExtension");
+  const extension = await NFTMarketplaceExtension.deploy();
+  await extension.deployed();
+  const NFTMarketplace = await ethers.getContractFactory(name);
+  const implementation = await NFTMarketplace.deploy(extension.address);
+  await implementation.deployed();
+  return implementation;
+}
+
+// The proxy answers to both the implementation's and the extension's functions
+async function marketplaceAt(address, name) {
+  const fragments = new Map();
+  for (const factoryName of [name, "NFTMarketplaceExtension"]) {
+    const factory = await ethers.getContractFactory(factoryName);
+    for (const fragment of factory.interface.fragments) {
+      if (fragment.type !== "constructor") {
+        fragments.set(`${fragment.type} ${fragment.format()}`, fragment);
+      }
+    }
+  }
+  const [signer] = await ethers.getSigners();
+  return new ethers.Contract(address, [...fragments.values()], signer);
+}
+
+async function deployMarketplace() {
+  const implementation = await deployImplementation("NFTMarketplace");
+  const NFTMarketplaceProxy = await ethers.getContractFactory("NFTMarketplaceProxy");
+  const proxy = await NFTMarketplaceProxy.deploy(
+    implementation.address,
+    implementation.interface.encodeFunctionData("initialize")
+  );
+  await proxy.deployed();
+  return marketplaceAt(proxy.address, "NFTMarketplace");
+}
+
+describe("NFTMarketplace", function () {
+  let market, nft, listingPrice, owner, seller, buyer, bidder;
+
+  beforeEach(async function () {
+    [owner, seller, buyer, bidder] = await ethers.getSigners();
+    market = await deployMarketplace();
+    const NFT = await ethers.getContractFactory("NFT");
+    nft = await NFT.deploy(market.address);
+    await nft.deployed();
+    await market.updateTokenContract(nft.address);
+    listingPrice = await market.getListingPrice();
+    for (const account of [seller, buyer, bidder]) {
+      await nft.connect(account).setApprovalForAll(market.address, true);
+    }
+  });
+
+  async function mintAndBuy(price) {
+    await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+    await market.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+  }
+
+  async function deployCollection(tokenIds) {
+    const MockERC721 = await ethers.getContractFactory("MockERC721");
+    const collection = await MockERC721.deploy("Other Collection", "OTHER");
+    await collection.deployed();
+    for (const tokenId of tokenIds) {
+      await collection.mint(seller.address, tokenId);
+    }
+    await collection.connect(seller).setApprovalForAll(market.address, true);
+    return collection;
+  }
+
+  describe("Own collection", function () {
+    const price = ethers.utils.parseEther("1");
+
+    it("Should escrow minted tokens and need the holder's approval to relist them", async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      expect(await nft.ownerOf(1)).to.equal(market.address);
+      await market.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+
+      await nft.connect(buyer).setApprovalForAll(market.address, false);
+      expect(await nft.isApprovedForAll(buyer.address, market.address)).to.equal(false);
+      await expect(market.connect(buyer).resellToken(nft.address, 1, price, { value: listingPrice }))
+        .to.be.revertedWith("ERC721: transfer caller is not owner nor approved");
+    });
+  });
+
+  describe("English auctions", function () {
+    const reserve = ethers.utils.parseEther("1");
+    const increment = ethers.utils.parseEther("0.1");
+
+    beforeEach(async function () {
+      await mintAndBuy(ethers.utils.parseEther("0.5"));
+      await market.connect(buyer).createAuction(nft.address, 1, reserve, increment, 3600, { value: listingPrice });
+    });
+
+    it("Should escrow the token and reject fixed-price purchases", async function () {
+      expect(await nft.ownerOf(1)).to.equal(market.address);
+      expect((await market.fetchMarketItems()).length).to.equal(1);
+      await expect(market.connect(bidder).createMarketSale(nft.address, 1, { value: reserve }))
+        .to.be.revertedWith("Item is listed as an auction");
+    });
+
+    it("Should enforce the reserve price and minimum increment", async function () {
+      await expect(market.connect(bidder).placeBid(nft.address, 1, { value: increment }))
+        .to.be.revertedWith("Bid must be at least the reserve price");
+      await market.connect(bidder).placeBid(nft.address, 1, { value: reserve });
+      await expect(market.connect(seller).placeBid(nft.address, 1, { value: reserve.add(1) }))
+        .to.be.revertedWith("Bid must exceed the highest bid by the minimum increment");
+    });
+
+    it("Should credit outbid bidders for withdrawal", async function () {
+      await market.connect(bidder).placeBid(nft.address, 1, { value: reserve });
+      await market.connect(seller).placeBid(nft.address, 1, { value: reserve.add(increment) });
+      expect(await market.getCredits(bidder.address)).to.equal(reserve);
+
+      await expect(() => market.connect(bidder).withdraw())
+        .to.changeEtherBalance(bidder, reserve);
+      expect(await market.getCredits(bidder.address)).to.equal(0);
+    });
+
+    it("Should extend the auction when bids arrive near the end", async function () {
+      await increaseTime(3600 - 60);
+      await market.connect(bidder).placeBid(nft.address, 1, { value: reserve });
+      const auction = await market.fetchAuction(nft.address, 1);
+      const block = await ethers.provider.getBlock("latest");
+      expect(auction.endTime).to.equal(block.timestamp + 600);
+    });
+
+    it("Should settle to the highest bidder and pay the seller", async function () {
+      await market.connect(bidder).placeBid(nft.address, 1, { value: reserve });
+      await expect(market.settleAuction(nft.address, 1)).to.be.revertedWith("Auction has not ended yet");
+      await increaseTime(3600);
+
+      const sellerCredits = await market.getCredits(seller.address);
+      const ownerCredits = await market.getCredits(owner.address);
+      await market.connect(owner).settleAuction(nft.address, 1);
+      expect(await market.getCredits(buyer.address)).to.equal(reserve.mul(95).div(100));
+      expect(await market.getCredits(seller.address)).to.equal(sellerCredits.add(reserve.mul(5).div(100)));
+      expect(await market.getCredits(owner.address)).to.equal(ownerCredits);
+      expect(await nft.ownerOf(1)).to.equal(bidder.address);
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+      expect((await market.connect(bidder).fetchMyNFTs()).length).to.equal(1);
+    });
+
+    it("Should return the token to the seller when there were no bids", async function () {
+      await increaseTime(3600);
+      await expect(market.settleAuction(nft.address, 1))
+        .to.emit(market, "AuctionSettled")
+        .withArgs(1, ethers.constants.AddressZero, 0);
+      expect(await nft.ownerOf(1)).to.equal(buyer.address);
+    });
+  });
+
+  describe("Dutch auctions", function () {
+    const startPrice = ethers.utils.parseEther("2");
+    const endPrice = ethers.utils.parseEther("1");
+
+    beforeEach(async function () {
+      await mintAndBuy(ethers.utils.parseEther("0.5"));
+      await market.connect(buyer).createDutchAuction(nft.address, 1, startPrice, endPrice, 1000, { value: listingPrice });
+    });
+
+    it("Should decay the price linearly to the end price", async function () {
+      expect(await market.getCurrentPrice(nft.address, 1)).to.be.lte(startPrice);
+      await increaseTime(500);
+      const price = await market.getCurrentPrice(nft.address, 1);
+      expect(price).to.be.lte(ethers.utils.parseEther("1.5"));
+      expect(price).to.be.gt(ethers.utils.parseEther("1.49"));
+      await increaseTime(1000);
+      expect(await market.getCurrentPrice(nft.address, 1)).to.equal(endPrice);
+    });
+
+    it("Should charge the current price and refund overpayment", async function () {
+      await increaseTime(2000);
+      await expect(() => market.connect(bidder).createMarketSale(nft.address, 1, { value: startPrice }))
+        .to.changeEtherBalance(bidder, startPrice.mul(-1));
+      expect(await market.getCredits(bidder.address)).to.equal(startPrice.sub(endPrice));
+      expect(await market.getCredits(buyer.address)).to.equal(endPrice.mul(95).div(100));
+      expect(await nft.ownerOf(1)).to.equal(bidder.address);
+      expect((await market.fetchDutchAuction(nft.address, 1)).duration).to.equal(0);
+    });
+
+    it("Should reject payments below the current price", async function () {
+      await expect(market.connect(bidder).createMarketSale(nft.address, 1, { value: endPrice }))
+        .to.be.revertedWith("Please submit at least the current price in order to complete the purchase");
+    });
+  });
+
+  describe("Cancelling listings", function () {
+    const price = ethers.utils.parseEther("1");
+
+    beforeEach(async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+    });
+
+    it("Should return the token to the seller and remove the listing", async function () {
+      await expect(market.connect(buyer).cancelListing(nft.address, 1))
+        .to.be.revertedWith("Only item seller can perform this operation");
+      await expect(market.connect(seller).cancelListing(nft.address, 1))
+        .to.emit(market, "MarketItemCancelled")
+        .withArgs(1, seller.address);
+
+      expect(await nft.ownerOf(1)).to.equal(seller.address);
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+      expect((await market.connect(seller).fetchMyNFTs()).length).to.equal(1);
+      await expect(market.connect(buyer).createMarketSale(nft.address, 1, { value: price }))
+        .to.be.revertedWith("Item is not listed");
+    });
+
+    it("Should allow a cancelled token to be listed again", async function () {
+      await market.connect(seller).cancelListing(nft.address, 1);
+      await market.connect(seller).resellToken(nft.address, 1, price, { value: listingPrice });
+      expect((await market.fetchMarketItems()).length).to.equal(1);
+    });
+  });
+
+  describe("Updating prices", function () {
+    const price = ethers.utils.parseEther("1");
+    const newPrice = ethers.utils.parseEther("2");
+
+    beforeEach(async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+    });
+
+    it("Should let the seller reprice an active listing", async function () {
+      await expect(market.connect(buyer).updateItemPrice(nft.address, 1, newPrice))
+        .to.be.revertedWith("Only item seller can perform this operation");
+      await expect(market.connect(seller).updateItemPrice(nft.address, 1, newPrice))
+        .to.emit(market, "PriceChanged")
+        .withArgs(1, seller.address, price, newPrice);
+
+      expect((await market.fetchMarketItems())[0].price).to.equal(newPrice);
+      await expect(market.connect(buyer).createMarketSale(nft.address, 1, { value: price }))
+        .to.be.revertedWith("Please submit the asking price in order to complete the purchase");
+    });
+
+    it("Should reject repricing once the item is sold", async function () {
+      await market.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+      await expect(market.connect(seller).updateItemPrice(nft.address, 1, newPrice))
+        .to.be.revertedWith("Item is not listed");
+    });
+  });
+
+  describe("External collections", function () {
+    const price = ethers.utils.parseEther("1");
+    let collection;
+
+    beforeEach(async function () {
+      collection = await deployCollection([1]);
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+    });
+
+    it("Should list and sell tokens of any ERC-721 collection keyed by collection and token id", async function () {
+      await collection.connect(seller).setApprovalForAll(market.address, false);
+      await expect(market.connect(seller).createMarketItem(collection.address, 1, price, { value: listingPrice }))
+        .to.be.revertedWith("ERC721: transfer caller is not owner nor approved");
+      await collection.connect(seller).approve(market.address, 1);
+      await expect(market.connect(seller).createMarketItem(collection.address, 1, price, { value: listingPrice }))
+        .to.emit(market, "MarketItemCreated")
+        .withArgs(2, collection.address, 1, seller.address, market.address, price, false);
+
+      const items = await market.fetchMarketItems();
+      expect(items.length).to.equal(2);
+      expect(items[1].nftContract).to.equal(collection.address);
+      expect((await market.connect(seller).fetchItemsListed()).length).to.equal(2);
+
+      await market.connect(buyer).createMarketSale(collection.address, 1, { value: price });
+      expect(await collection.ownerOf(1)).to.equal(buyer.address);
+      expect(await nft.ownerOf(1)).to.equal(market.address);
+      expect(await market.getCredits(seller.address)).to.equal(price);
+      expect((await market.connect(buyer).fetchMyNFTs())[0].nftContract).to.equal(collection.address);
+    });
+  });
+
+  describe("Royalties", function () {
+    const price = ethers.utils.parseEther("1");
+
+    it("Should report the default creator royalty via ERC-2981", async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      const [receiver, amount] = await nft.royaltyInfo(1, price);
+      expect(receiver).to.equal(seller.address);
+      expect(amount).to.equal(price.mul(5).div(100));
+      expect(await nft.supportsInterface("0x2a55205a")).to.equal(true);
+    });
+
+    it("Should pay per-token royalty overrides to the creator on resale", async function () {
+      await market.connect(seller).createTokenWithRoyalty("https://www.mytokenlocation.com", price, 1000, { value: listingPrice });
+      await market.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+      await market.connect(buyer).resellToken(nft.address, 1, price, { value: listingPrice });
+
+      const sellerCredits = await market.getCredits(seller.address);
+      await market.connect(bidder).createMarketSale(nft.address, 1, { value: price });
+      expect(await market.getCredits(seller.address)).to.equal(sellerCredits.add(price.div(10)));
+      expect(await market.getCredits(buyer.address)).to.equal(price.mul(9).div(10));
+    });
+
+    it("Should only let the marketplace owner change the default royalty", async function () {
+      await expect(market.connect(seller).updateDefaultRoyalty(0))
+        .to.be.revertedWith("Only fee manager can update default royalty.");
+      await market.updateDefaultRoyalty(250);
+      expect(await market.getDefaultRoyalty()).to.equal(250);
+    });
+
+    it("Should cap royalties to leave room for the maximum marketplace fee", async function () {
+      await expect(market.updateDefaultRoyalty(9001))
+        .to.be.revertedWith("Royalty must leave room for the maximum marketplace fee");
+      await expect(market.connect(seller).createTokenWithRoyalty("https://www.mytokenlocation.com", price, 9001, { value: listingPrice }))
+        .to.be.revertedWith("Royalty must leave room for the maximum marketplace fee");
+
+      await market.connect(seller).createTokenWithRoyalty("https://www.mytokenlocation.com", price, 9000, { value: listingPrice });
+      await market.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+      await market.connect(buyer).resellToken(nft.address, 1, price, { value: listingPrice });
+      await market.updateMarketplaceFee(1000);
+      await market.connect(bidder).createMarketSale(nft.address, 1, { value: price });
+      expect(await nft.ownerOf(1)).to.equal(bidder.address);
+    });
+
+    it("Should sell tokens of collections that do not implement ERC-165", async function () {
+      const MockLegacyERC721 = await ethers.getContractFactory("MockLegacyERC721");
+      const legacy = await MockLegacyERC721.deploy();
+      await legacy.deployed();
+      await legacy.mint(seller.address, 1);
+      await legacy.connect(seller).approve(market.address, 1);
+      await market.connect(seller).createMarketItem(legacy.address, 1, price, { value: listingPrice });
+
+      await market.connect(buyer).createMarketSale(legacy.address, 1, { value: price });
+      expect(await legacy.ownerOf(1)).to.equal(buyer.address);
+      expect(await market.getCredits(seller.address)).to.equal(price);
+    });
+  });
+
+  describe("ERC-20 currencies", function () {
+    const price = ethers.utils.parseEther("10");
+    let token;
+
+    beforeEach(async function () {
+      const MockERC20 = await ethers.getContractFactory("MockERC20");
+      token = await MockERC20.deploy("Wrapped Ether", "WETH");
+      await token.deployed();
+      await token.mint(bidder.address, price);
+      await mintAndBuy(ethers.utils.parseEther("1"));
+    });
+
+    it("Should only list in allowlisted currencies", async function () {
+      await expect(market.connect(buyer).resellTokenForCurrency(nft.address, 1, price, token.address, { value: listingPrice }))
+        .to.be.revertedWith("Currency is not allowed");
+      await expect(market.connect(seller).updateCurrencyAllowed(token.address, true))
+        .to.be.revertedWith("Only moderator can update allowed currencies.");
+    });
+
+    it("Should charge the buyer in the listing currency", async function () {
+      await market.updateCurrencyAllowed(token.address, true);
+      await market.connect(buyer).resellTokenForCurrency(nft.address, 1, price, token.address, { value: listingPrice });
+      expect((await market.fetchMarketItems())[0].currency).to.equal(token.address);
+
+      await expect(market.connect(bidder).createMarketSale(nft.address, 1, { value: price }))
+        .to.be.revertedWith("Item is priced in an ERC-20 currency");
+      await token.connect(bidder).approve(market.address, price);
+      await market.connect(bidder).createMarketSale(nft.address, 1);
+
+      expect(await nft.ownerOf(1)).to.equal(bidder.address);
+      expect(await token.balanceOf(buyer.address)).to.equal(price.mul(95).div(100));
+      expect(await token.balanceOf(seller.address)).to.equal(price.mul(5).div(100));
+    });
+  });
+
+  describe("Withdrawals", function () {
+    const price = ethers.utils.parseEther("1");
+
+    it("Should credit sale proceeds and fees instead of pushing them", async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      await expect(market.connect(buyer).createMarketSale(nft.address, 1, { value: price }))
+        .to.emit(market, "FundsCredited")
+        .withArgs(seller.address, price);
+      expect(await market.getCredits(seller.address)).to.equal(price);
+      expect(await market.getCredits(owner.address)).to.equal(listingPrice);
+
+      await expect(() => market.connect(seller).withdraw())
+        .to.changeEtherBalance(seller, price);
+      await expect(market.connect(owner).withdraw())
+        .to.emit(market, "FundsWithdrawn")
+        .withArgs(owner.address, listingPrice);
+      await expect(market.connect(seller).withdraw())
+        .to.be.revertedWith("No funds to withdraw");
+    });
+  });
+
+  describe("Signed orders", function () {
+    const price = ethers.utils.parseEther("1");
+    const orderTypes = {
+      Order: [
+        { name: "maker", type: "address" },
+        { name: "isOffer", type: "bool" },
+        { name: "nftContract", type: "address" },
+        { name: "tokenId", type: "uint256" },
+        { name: "price", type: "uint256" },
+        { name: "currency", type: "address" },
+        { name: "expiry", type: "uint256" },
+        { name: "nonce", type: "uint256" },
+        { name: "counter", type: "uint256" },
+      ],
+    };
+    let order;
+
+    async function signOrder(signer, value) {
+      const domain = {
+        name: "NFTMarketplace",
+        version: "1",
+        chainId: (await ethers.provider.getNetwork()).chainId,
+        verifyingContract: market.address,
+      };
+      return signer._signTypedData(domain, orderTypes, value);
+    }
+
+    beforeEach(async function () {
+      await mintAndBuy(price);
+      const block = await ethers.provider.getBlock("latest");
+      order = {
+        maker: buyer.address,
+        isOffer: false,
+        nftContract: nft.address,
+        tokenId: 1,
+        price: price,
+        currency: ethers.constants.AddressZero,
+        expiry: block.timestamp + 3600,
+        nonce: 0,
+        counter: 0,
+      };
+    });
+
+    it("Should sell a token held by the signer without escrow", async function () {
+      const signature = await signOrder(buyer, order);
+      await expect(market.connect(bidder).fulfillOrder(order, signature, { value: price }))
+        .to.emit(market, "OrderFulfilled");
+      expect(await nft.ownerOf(1)).to.equal(bidder.address);
+      expect(await market.getCredits(buyer.address)).to.equal(price.mul(95).div(100));
+
+      await expect(market.connect(seller).fulfillOrder(order, signature, { value: price }))
+        .to.be.revertedWith("Order has already been used or cancelled");
+    });
+
+    it("Should reject orders signed by someone else", async function () {
+      const signature = await signOrder(bidder, order);
+      await expect(market.connect(bidder).fulfillOrder(order, signature, { value: price }))
+        .to.be.revertedWith("Invalid order signature");
+    });
+
+    it("Should honour nonce and counter cancellations", async function () {
+      const signature = await signOrder(buyer, order);
+      await market.connect(buyer).cancelOrder(0);
+      await expect(market.connect(bidder).fulfillOrder(order, signature, { value: price }))
+        .to.be.revertedWith("Order has already been used or cancelled");
+
+      const nextOrder = { ...order, nonce: 1 };
+      const nextSignature = await signOrder(buyer, nextOrder);
+      await market.connect(buyer).incrementOrderCounter();
+      await expect(market.connect(bidder).fulfillOrder(nextOrder, nextSignature, { value: price }))
+        .to.be.revertedWith("Order has been cancelled");
+    });
+
+    it("Should let the owner accept a signed ERC-20 offer", async function () {
+      const MockERC20 = await ethers.getContractFactory("MockERC20");
+      const token = await MockERC20.deploy("Wrapped Ether", "WETH");
+      await token.mint(bidder.address, price);
+      await token.connect(bidder).approve(market.address, price);
+      await market.updateCurrencyAllowed(token.address, true);
+
+      const offer = { ...order, maker: bidder.address, isOffer: true, currency: token.address };
+      const signature = await signOrder(bidder, offer);
+      await market.connect(buyer).fulfillOrder(offer, signature);
+      expect(await nft.ownerOf(1)).to.equal(bidder.address);
+      expect(await token.balanceOf(buyer.address)).to.equal(price.mul(95).div(100));
+    });
+  });
+
+  describe("Offers", function () {
+    const price = ethers.utils.parseEther("1");
+    const amount = ethers.utils.parseEther("2");
+    let expiry;
+
+    beforeEach(async function () {
+      await mintAndBuy(price);
+      expiry = (await ethers.provider.getBlock("latest")).timestamp + 3600;
+      await market.connect(bidder).makeOffer(nft.address, 1, expiry, { value: amount });
+    });
+
+    it("Should let the owner accept an offer on an unlisted token", async function () {
+      expect((await market.fetchOffers(nft.address, 1)).length).to.equal(1);
+      await expect(market.connect(seller).acceptOffer(1, { value: listingPrice }))
+        .to.be.revertedWith("Only token owner can perform this operation");
+
+      await expect(market.connect(buyer).acceptOffer(1, { value: listingPrice }))
+        .to.emit(market, "OfferAccepted")
+        .withArgs(1, 1, buyer.address, bidder.address, amount);
+      expect(await nft.ownerOf(1)).to.equal(bidder.address);
+      expect(await market.getCredits(buyer.address)).to.equal(amount.mul(95).div(100));
+      expect((await market.fetchOffers(nft.address, 1)).length).to.equal(0);
+    });
+
+    it("Should refund cancelled offers and reject expired ones", async function () {
+      await market.connect(bidder).makeOffer(nft.address, 1, expiry, { value: amount });
+      await market.connect(bidder).cancelOffer(1);
+      expect(await market.getCredits(bidder.address)).to.equal(amount);
+      await expect(market.connect(buyer).acceptOffer(1, { value: listingPrice }))
+        .to.be.revertedWith("Offer is not active");
+
+      await increaseTime(3600);
+      expect((await market.fetchOffers(nft.address, 1)).length).to.equal(0);
+      await expect(market.connect(buyer).acceptOffer(2, { value: listingPrice }))
+        .to.be.revertedWith("Offer has expired");
+    });
+  });
+
+  describe("Batch purchases", function () {
+    const price = ethers.utils.parseEther("1");
+
+    beforeEach(async function () {
+      for (let i = 0; i < 3; i++) {
+        await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      }
+      await market.connect(bidder).createMarketSale(nft.address, 2, { value: price });
+    });
+
+    it("Should revert all-or-nothing purchases when an item is sold", async function () {
+      await expect(market.connect(buyer).createMarketSales(Array(3).fill(nft.address), [1, 2, 3], [price, price, price], false, { value: price.mul(3) }))
+        .to.be.revertedWith("Item is no longer available at the expected price");
+    });
+
+    it("Should skip unavailable items and refund them on partial fill", async function () {
+      await market.connect(buyer).createMarketSales(Array(3).fill(nft.address), [1, 2, 3], [price, price, price], true, { value: price.mul(3) });
+      expect(await nft.ownerOf(1)).to.equal(buyer.address);
+      expect(await nft.ownerOf(3)).to.equal(buyer.address);
+      expect(await market.getCredits(buyer.address)).to.equal(price);
+    });
+
+    it("Should require the summed price", async function () {
+      await expect(market.connect(buyer).createMarketSales([nft.address, nft.address], [1, 3], [price, price], false, { value: price }))
+        .to.be.revertedWith("Please submit the asking price in order to complete the purchase");
+    });
+  });
+
+  describe("Batch minting", function () {
+    const uris = ["https://www.mytokenlocation.com", "https://www.mytokenlocation2.com"];
+    const prices = [ethers.utils.parseEther("1"), ethers.utils.parseEther("2")];
+
+    it("Should mint and list every token for the listing price times the count", async function () {
+      await expect(market.connect(seller).createTokens(uris, prices, { value: listingPrice }))
+        .to.be.revertedWith("Price must be equal to listing price times the number of tokens");
+
+      const tx = market.connect(seller).createTokens(uris, prices, { value: listingPrice.mul(2) });
+      await expect(tx).to.emit(market, "MarketItemCreated").withArgs(2, nft.address, 2, seller.address, market.address, prices[1], false);
+      const items = await market.fetchMarketItems();
+      expect(items.length).to.equal(2);
+      expect(await nft.tokenURI(2)).to.equal(uris[1]);
+    });
+
+    it("Should enforce the configurable batch size cap", async function () {
+      await expect(market.connect(seller).updateMaxBatchSize(1))
+        .to.be.revertedWith("Only marketplace owner can update max batch size.");
+      await market.updateMaxBatchSize(1);
+      await expect(market.connect(seller).createTokens(uris, prices, { value: listingPrice.mul(2) }))
+        .to.be.revertedWith("Batch exceeds the maximum batch size");
+    });
+  });
+
+  describe("Lazy minting", function () {
+    const price = ethers.utils.parseEther("1");
+    const voucherTypes = {
+      MintVoucher: [
+        { name: "creator", type: "address" },
+        { name: "tokenURI", type: "string" },
+        { name: "price", type: "uint256" },
+        { name: "nonce", type: "uint256" },
+      ],
+    };
+    let voucher, signature;
+
+    beforeEach(async function () {
+      voucher = { creator: seller.address, tokenURI: "https://www.mytokenlocation.com", price: price, nonce: 7 };
+      const domain = {
+        name: "NFTMarketplace",
+        version: "1",
+        chainId: (await ethers.provider.getNetwork()).chainId,
+        verifyingContract: market.address,
+      };
+      signature = await seller._signTypedData(domain, voucherTypes, voucher);
+    });
+
+    it("Should mint to the buyer and pay the creator and marketplace", async function () {
+      await expect(market.connect(buyer).redeemVoucher(voucher, signature, { value: price }))
+        .to.emit(market, "VoucherRedeemed")
+        .withArgs(1, seller.address, buyer.address, price, 7);
+
+      expect(await nft.ownerOf(1)).to.equal(buyer.address);
+      expect(await nft.tokenURI(1)).to.equal(voucher.tokenURI);
+      expect(await market.getCredits(seller.address)).to.equal(price.sub(listingPrice));
+      expect(await market.getCredits(owner.address)).to.equal(listingPrice);
+      expect((await market.connect(buyer).fetchMyNFTs()).length).to.equal(1);
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+    });
+
+    it("Should report only the marketplace fee in MarketItemSold", async function () {
+      await market.updateMarketplaceFee(250);
+      await expect(market.connect(buyer).redeemVoucher(voucher, signature, { value: price }))
+        .to.emit(market, "MarketItemSold")
+        .withArgs(1, seller.address, buyer.address, nft.address, 1, ethers.constants.AddressZero, price, price.mul(250).div(10000), 0);
+      expect(await market.getCredits(owner.address)).to.equal(listingPrice.add(price.mul(250).div(10000)));
+    });
+
+    it("Should reject replayed, cancelled and tampered vouchers", async function () {
+      await expect(market.connect(buyer).redeemVoucher({ ...voucher, price: listingPrice }, signature, { value: listingPrice }))
+        .to.be.revertedWith("Invalid voucher signature");
+
+      await market.connect(buyer).redeemVoucher(voucher, signature, { value: price });
+      await expect(market.connect(bidder).redeemVoucher(voucher, signature, { value: price }))
+        .to.be.revertedWith("Voucher has already been redeemed or cancelled");
+
+      const next = { ...voucher, nonce: 8 };
+      await market.connect(seller).cancelMintVoucher(8);
+      await expect(market.connect(bidder).redeemVoucher(next, signature, { value: price }))
+        .to.be.revertedWith("Voucher has already been redeemed or cancelled");
+    });
+  });
+
+  describe("ERC-1155 editions", function () {
+    const pricePerUnit = ethers.utils.parseEther("0.5");
+    let editions;
+
+    beforeEach(async function () {
+      const MockERC1155 = await ethers.getContractFactory("MockERC1155");
+      editions = await MockERC1155.deploy();
+      await editions.deployed();
+      await editions.mint(seller.address, 7, 10);
+      await editions.connect(seller).setApprovalForAll(market.address, true);
+      await market.connect(seller).createEditionListing(editions.address, 7, 10, pricePerUnit, { value: listingPrice });
+    });
+
+    it("Should sell part of a listing at the per-unit price", async function () {
+      await expect(market.connect(buyer).buyEditions(1, 3, { value: pricePerUnit.mul(3) }))
+        .to.emit(market, "EditionsPurchased")
+        .withArgs(1, buyer.address, 3, 7, pricePerUnit.mul(3));
+
+      expect(await editions.balanceOf(buyer.address, 7)).to.equal(3);
+      expect(await editions.balanceOf(market.address, 7)).to.equal(7);
+      expect(await market.getCredits(seller.address)).to.equal(pricePerUnit.mul(3));
+
+      const [listing] = await market.fetchMarketItems();
+      expect(listing.quantity).to.equal(7);
+      const [purchase] = await market.connect(buyer).fetchMyNFTs();
+      expect(purchase.quantity).to.equal(3);
+    });
+
+    it("Should close the listing once every edition is sold", async function () {
+      await expect(market.connect(buyer).buyEditions(1, 11, { value: pricePerUnit.mul(11) }))
+        .to.be.revertedWith("Not enough editions available");
+      await market.connect(buyer).buyEditions(1, 10, { value: pricePerUnit.mul(10) });
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+      await expect(market.connect(bidder).buyEditions(1, 1, { value: pricePerUnit }))
+        .to.be.revertedWith("Item is not listed");
+    });
+
+    it("Should return the remaining editions on cancellation", async function () {
+      await market.connect(buyer).buyEditions(1, 4, { value: pricePerUnit.mul(4) });
+      await market.connect(seller).cancelEditionListing(1);
+      expect(await editions.balanceOf(seller.address, 7)).to.equal(6);
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+    });
+
+    it("Should draw the buyer's purchase record down when they relist editions", async function () {
+      await market.connect(buyer).buyEditions(1, 3, { value: pricePerUnit.mul(3) });
+      await editions.connect(buyer).setApprovalForAll(market.address, true);
+
+      await market.connect(buyer).createEditionListing(editions.address, 7, 2, pricePerUnit, { value: listingPrice });
+      const [purchase] = await market.connect(buyer).fetchMyNFTs();
+      expect(purchase.quantity).to.equal(1);
+
+      await market.connect(buyer).createEditionListing(editions.address, 7, 1, pricePerUnit, { value: listingPrice });
+      expect((await market.connect(buyer).fetchMyNFTs()).length).to.equal(0);
+      expect((await market.connect(buyer).fetchItemsListed()).length).to.equal(2);
+    });
+  });
+
+  describe("Marketplace fees", function () {
+    const price = ethers.utils.parseEther("1");
+
+    it("Should only let the marketplace owner set a fee within the ceiling", async function () {
+      await expect(market.connect(seller).updateMarketplaceFee(250))
+        .to.be.revertedWith("Only fee manager can update marketplace fee.");
+      await expect(market.updateMarketplaceFee(1001))
+        .to.be.revertedWith("Marketplace fee must not exceed the maximum fee");
+      await expect(market.updateMarketplaceFee(250))
+        .to.emit(market, "MarketplaceFeeUpdated")
+        .withArgs(250);
+      expect(await market.getMarketplaceFee()).to.equal(250);
+    });
+
+    it("Should split a resale between the marketplace fee, royalty and seller", async function () {
+      await market.updateMarketplaceFee(250);
+      await mintAndBuy(price);
+      await market.connect(buyer).resellToken(nft.address, 1, price, { value: listingPrice });
+
+      const ownerCredits = await market.getCredits(owner.address);
+      const sellerCredits = await market.getCredits(seller.address);
+      await expect(market.connect(bidder).createMarketSale(nft.address, 1, { value: price }))
+        .to.emit(market, "MarketplaceFeePaid")
+        .withArgs(1, owner.address, price.mul(250).div(10000));
+
+      expect(await market.getCredits(owner.address)).to.equal(ownerCredits.add(price.mul(250).div(10000)));
+      expect(await market.getCredits(seller.address)).to.equal(sellerCredits.add(price.mul(5).div(100)));
+      expect(await market.getCredits(buyer.address)).to.equal(price.mul(9250).div(10000));
+    });
+
+    it("Should credit fees to a separate fee recipient", async function () {
+      await expect(market.connect(seller).updateFeeRecipient(bidder.address))
+        .to.be.revertedWith("Only fee manager can update fee recipient.");
+      await market.updateFeeRecipient(bidder.address);
+      await market.updateMarketplaceFee(100);
+      await mintAndBuy(price);
+
+      expect(await market.getFeeRecipient()).to.equal(bidder.address);
+      expect(await market.getCredits(bidder.address)).to.equal(listingPrice.add(price.div(100)));
+      expect(await market.getCredits(owner.address)).to.equal(0);
+    });
+
+    it("Should keep the listing price paid at listing time when it changes before the listing closes", async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      expect(await market.getCredits(owner.address)).to.equal(listingPrice);
+
+      await market.updateListingPrice(price);
+      await market.connect(seller).cancelListing(nft.address, 1);
+      expect(await market.getCredits(owner.address)).to.equal(listingPrice);
+    });
+  });
+
+  describe("Access control", function () {
+    it("Should gate fee and moderation settings behind their roles", async function () {
+      const feeManagerRole = await market.FEE_MANAGER_ROLE();
+      await expect(market.connect(seller).updateListingPrice(1))
+        .to.be.revertedWith("Only fee manager can update listing price.");
+      await expect(market.grantRole(feeManagerRole, seller.address))
+        .to.emit(market, "RoleGranted")
+        .withArgs(feeManagerRole, seller.address, owner.address);
+      await market.connect(seller).updateListingPrice(1);
+      expect(await market.getListingPrice()).to.equal(1);
+      await expect(market.connect(seller).updateCurrencyAllowed(buyer.address, true))
+        .to.be.revertedWith("Only moderator can update allowed currencies.");
+
+      await expect(market.revokeRole(feeManagerRole, seller.address))
+        .to.emit(market, "RoleRevoked")
+        .withArgs(feeManagerRole, seller.address, owner.address);
+      await expect(market.connect(seller).updateListingPrice(2))
+        .to.be.revertedWith("Only fee manager can update listing price.");
+    });
+
+    it("Should only transfer ownership once the new owner accepts it", async function () {
+      const adminRole = await market.DEFAULT_ADMIN_ROLE();
+      await expect(market.connect(seller).transferOwnership(seller.address))
+        .to.be.revertedWith("Only marketplace owner can transfer ownership.");
+      await expect(market.transferOwnership(owner.address))
+        .to.be.revertedWith("Marketplace is already owned by this address");
+      await expect(market.transferOwnership(seller.address))
+        .to.emit(market, "OwnershipTransferStarted")
+        .withArgs(owner.address, seller.address);
+      expect(await market.getOwner()).to.equal(owner.address);
+      expect(await market.getPendingOwner()).to.equal(seller.address);
+      await expect(market.connect(buyer).acceptOwnership())
+        .to.be.revertedWith("Only the pending owner can accept ownership");
+
+      await expect(market.connect(seller).acceptOwnership())
+        .to.emit(market, "OwnershipTransferred")
+        .withArgs(owner.address, seller.address);
+      expect(await market.getOwner()).to.equal(seller.address);
+      expect(await market.hasRole(adminRole, seller.address)).to.equal(true);
+      expect(await market.hasRole(adminRole, owner.address)).to.equal(false);
+      await expect(market.updateMaxBatchSize(10))
+        .to.be.revertedWith("Only marketplace owner can update max batch size.");
+    });
+
+    it("Should move the previous owner's operational roles to the new owner", async function () {
+      const roles = await Promise.all([
+        market.FEE_MANAGER_ROLE(),
+        market.PAUSER_ROLE(),
+        market.MODERATOR_ROLE(),
+        market.UPGRADER_ROLE(),
+      ]);
+      await market.grantRole(roles[1], bidder.address);
+      await market.transferOwnership(seller.address);
+      await market.connect(seller).acceptOwnership();
+
+      for (const role of roles) {
+        expect(await market.hasRole(role, seller.address)).to.equal(true);
+        expect(await market.hasRole(role, owner.address)).to.equal(false);
+      }
+      expect(await market.hasRole(roles[1], bidder.address)).to.equal(true);
+      await expect(market.updatePaused(true, true, true))
+        .to.be.revertedWith("Only pauser can update paused state.");
+      await expect(market.upgradeTo(market.address))
+        .to.be.revertedWith("Only upgrader can upgrade the marketplace.");
+    });
+
+    it("Should not let the owner's admin role be revoked directly", async function () {
+      const adminRole = await market.DEFAULT_ADMIN_ROLE();
+      await expect(market.renounceRole(adminRole, owner.address))
+        .to.be.revertedWith("Ownership must be transferred to remove the owner's admin role");
+    });
+  });
+
+  describe("Pausing", function () {
+    const price = ethers.utils.parseEther("1");
+
+    it("Should only let pausers pause trading", async function () {
+      await expect(market.connect(seller).updatePaused(true, true, true))
+        .to.be.revertedWith("Only pauser can update paused state.");
+      await expect(market.updatePaused(true, false, true))
+        .to.emit(market, "PausedUpdated")
+        .withArgs(true, false, true);
+      const [mintingPaused, listingPaused, buyingPaused] = await market.getPaused();
+      expect(mintingPaused).to.equal(true);
+      expect(listingPaused).to.equal(false);
+      expect(buyingPaused).to.equal(true);
+    });
+
+    it("Should block minting, listing and buying separately", async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+
+      await market.updatePaused(true, false, false);
+      await expect(market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice }))
+        .to.be.revertedWith("Minting is paused");
+
+      await market.updatePaused(false, false, true);
+      await expect(market.connect(buyer).createMarketSale(nft.address, 1, { value: price }))
+        .to.be.revertedWith("Buying is paused");
+
+      await market.updatePaused(false, true, false);
+      await market.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+      await expect(market.connect(buyer).resellToken(nft.address, 1, price, { value: listingPrice }))
+        .to.be.revertedWith("Listing is paused");
+    });
+
+    it("Should still allow cancelling listings and withdrawing while paused", async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      await market.updatePaused(true, true, true);
+
+      await market.connect(seller).cancelListing(nft.address, 1);
+      expect(await nft.ownerOf(1)).to.equal(seller.address);
+      await expect(() => market.connect(owner).withdraw())
+        .to.changeEtherBalance(owner, listingPrice);
+    });
+  });
+
+  describe("Upgrades", function () {
+    const price = ethers.utils.parseEther("1");
+
+    it("Should keep listed items and escrowed tokens across an upgrade", async function () {
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      await market.updateMarketplaceFee(250);
+      const itemBefore = await market.fetchMarketItem(1);
+
+      const implementation = await deployImplementation("NFTMarketplaceV2");
+      await market.upgradeTo(implementation.address);
+      const upgraded = await marketplaceAt(market.address, "NFTMarketplaceV2");
+      expect(await upgraded.version()).to.equal("2");
+
+      const itemAfter = await upgraded.fetchMarketItem(1);
+      expect(itemAfter.seller).to.equal(itemBefore.seller);
+      expect(itemAfter.owner).to.equal(market.address);
+      expect(itemAfter.price).to.equal(price);
+      expect(await upgraded.getItemId(nft.address, 1)).to.equal(1);
+      expect(await upgraded.getMarketplaceFee()).to.equal(250);
+      expect(await upgraded.getListingPrice()).to.equal(listingPrice);
+      expect(await nft.ownerOf(1)).to.equal(market.address);
+
+      await upgraded.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+      expect(await nft.ownerOf(1)).to.equal(buyer.address);
+    });
+
+    it("Should only let upgraders upgrade and never re-initialize", async function () {
+      const implementation = await deployImplementation("NFTMarketplaceV2");
+      await expect(market.connect(seller).upgradeTo(implementation.address))
+        .to.be.revertedWith("Only upgrader can upgrade the marketplace.");
+      await expect(market.connect(seller).initialize())
+        .to.be.revertedWith("Initializable: contract is already initialized");
+      await expect(implementation.initialize())
+        .to.be.revertedWith("Initializable: contract is already initialized");
+    });
+  });
+
+  describe("Paginated queries", function () {
+    const price = ethers.utils.parseEther("1");
+
+    beforeEach(async function () {
+      const uris = Array(5).fill("https://www.mytokenlocation.com");
+      await market.connect(seller).createTokens(uris, Array(5).fill(price), { value: listingPrice.mul(5) });
+      await market.connect(buyer).createMarketSale(nft.address, 2, { value: price });
+      await market.connect(buyer).createMarketSale(nft.address, 4, { value: price });
+    });
+
+    it("Should report item counts", async function () {
+      expect(await market.getItemCount()).to.equal(5);
+      expect(await market.getListedItemCount()).to.equal(3);
+    });
+
+    it("Should page through listed items with a cursor", async function () {
+      let [items, nextCursor] = await market.fetchMarketItemsPage(0, 2);
+      expect(items.map((item) => item.tokenId.toNumber())).to.deep.equal([1, 5]);
+      expect(nextCursor).to.equal(2);
+
+      [items, nextCursor] = await market.fetchMarketItemsPage(nextCursor, 2);
+      expect(items.map((item) => item.tokenId.toNumber())).to.deep.equal([3]);
+      expect(nextCursor).to.equal(0);
+
+      await expect(market.fetchMarketItemsPage(0, 0))
+        .to.be.revertedWith("Limit must be at least 1");
+    });
+
+    it("Should accept any cursor and limit without overflowing", async function () {
+      let [items, nextCursor] = await market.fetchMarketItemsPage(1, ethers.constants.MaxUint256);
+      expect(items.map((item) => item.tokenId.toNumber())).to.deep.equal([5, 3]);
+      expect(nextCursor).to.equal(0);
+
+      [items, nextCursor] = await market.fetchMarketItemsPage(ethers.constants.MaxUint256, ethers.constants.MaxUint256);
+      expect(items.length).to.equal(0);
+      expect(nextCursor).to.equal(0);
+    });
+
+    it("Should page through the caller's purchased and listed items", async function () {
+      const [owned, ownedCursor] = await market.connect(buyer).fetchMyNFTsPage(0, 10);
+      expect(owned.map((item) => item.tokenId.toNumber())).to.deep.equal([2, 4]);
+      expect(ownedCursor).to.equal(0);
+
+      const [listed] = await market.connect(seller).fetchItemsListedPage(1, 5);
+      expect(listed.map((item) => item.tokenId.toNumber())).to.deep.equal([5, 3]);
+    });
+
+    it("Should keep the listing indexes in sync with sales and cancellations", async function () {
+      await market.connect(seller).cancelListing(nft.address, 1);
+      expect((await market.fetchMarketItems()).map((item) => item.tokenId.toNumber())).to.deep.equal([3, 5]);
+      expect((await market.connect(seller).fetchItemsListed()).length).to.equal(2);
+      expect((await market.connect(seller).fetchMyNFTs()).map((item) => item.tokenId.toNumber())).to.deep.equal([1]);
+
+      await market.connect(buyer).resellToken(nft.address, 2, price, { value: listingPrice });
+      expect((await market.connect(buyer).fetchMyNFTs()).map((item) => item.tokenId.toNumber())).to.deep.equal([4]);
+      expect((await market.connect(buyer).fetchItemsListed()).map((item) => item.tokenId.toNumber())).to.deep.equal([2]);
+      expect(await market.getListedItemCount()).to.equal(3);
+    });
+  });
+
+  describe("Lifecycle events", function () {
+    const price = ethers.utils.parseEther("1");
+
+    it("Should emit ListingPriceUpdated with the old and new price", async function () {
+      await expect(market.updateListingPrice(listingPrice.mul(2)))
+        .to.emit(market, "ListingPriceUpdated")
+        .withArgs(listingPrice, listingPrice.mul(2));
+    });
+
+    it("Should emit MarketItemSold with the fee and royalty split", async function () {
+      await market.updateMarketplaceFee(250);
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      await expect(market.connect(buyer).createMarketSale(nft.address, 1, { value: price }))
+        .to.emit(market, "MarketItemSold")
+        .withArgs(1, seller.address, buyer.address, nft.address, 1, ethers.constants.AddressZero, price, price.mul(250).div(10000), 0);
+      expect((await market.fetchMarketItem(1)).sold).to.equal(true);
+
+      await expect(market.connect(buyer).resellToken(nft.address, 1, price.mul(2), { value: listingPrice }))
+        .to.emit(market, "MarketItemRelisted")
+        .withArgs(1, nft.address, 1, buyer.address, price.mul(2), ethers.constants.AddressZero);
+      expect((await market.fetchMarketItem(1)).sold).to.equal(false);
+
+      await expect(market.connect(bidder).createMarketSale(nft.address, 1, { value: price.mul(2) }))
+        .to.emit(market, "MarketItemSold")
+        .withArgs(1, buyer.address, bidder.address, nft.address, 1, ethers.constants.AddressZero, price.mul(2), price.mul(500).div(10000), price.mul(10).div(100));
+    });
+
+    it("Should emit exactly one listing event for every listing", async function () {
+      const listingEvents = async (tx) => (await (await tx).wait()).events
+        .map((event) => event.event)
+        .filter((name) => name === "MarketItemCreated" || name === "MarketItemRelisted");
+
+      await market.connect(seller).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      await market.connect(seller).cancelListing(nft.address, 1);
+      const relisting = market.connect(seller).resellToken(nft.address, 1, price, { value: listingPrice });
+      await expect(relisting)
+        .to.emit(market, "MarketItemCreated")
+        .withArgs(1, nft.address, 1, seller.address, market.address, price, false);
+      expect(await listingEvents(relisting)).to.deep.equal(["MarketItemCreated"]);
+
+      await market.connect(buyer).createMarketSale(nft.address, 1, { value: price });
+      const resale = market.connect(buyer).createMarketItem(nft.address, 1, price, { value: listingPrice });
+      await expect(resale)
+        .to.emit(market, "MarketItemRelisted")
+        .withArgs(1, nft.address, 1, buyer.address, price, ethers.constants.AddressZero);
+      expect(await listingEvents(resale)).to.deep.equal(["MarketItemRelisted"]);
+    });
+  });
+
+  describe("Listing expiry", function () {
+    const price = ethers.utils.parseEther("1");
+    let collection;
+
+    beforeEach(async function () {
+      collection = await deployCollection([1, 2]);
+      const { timestamp } = await ethers.provider.getBlock("latest");
+      await market.connect(seller).createMarketItemWithExpiry(collection.address, 1, price, timestamp + 3600, { value: listingPrice });
+    });
+
+    it("Should reject expiries in the past", async function () {
+      await expect(market.connect(seller).createMarketItemWithExpiry(collection.address, 2, price, 1, { value: listingPrice }))
+        .to.be.revertedWith("Expiry must be in the future");
+    });
+
+    it("Should stop selling and showing the item once it expires", async function () {
+      expect((await market.fetchMarketItems()).length).to.equal(1);
+      await expect(market.reclaimExpiredItem(collection.address, 1))
+        .to.be.revertedWith("Listing has not expired");
+
+      await increaseTime(3600);
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+      await expect(market.connect(buyer).createMarketSale(collection.address, 1, { value: price }))
+        .to.be.revertedWith("Listing has expired");
+    });
+
+    it("Should let anyone return an expired item to its seller", async function () {
+      await increaseTime(3600);
+      await expect(market.connect(buyer).reclaimExpiredItem(collection.address, 1))
+        .to.emit(market, "MarketItemExpired")
+        .withArgs(1, seller.address, buyer.address);
+
+      expect(await collection.ownerOf(1)).to.equal(seller.address);
+      expect(await market.getListedItemCount()).to.equal(0);
+      expect((await market.connect(seller).fetchMyNFTs()).length).to.equal(1);
+      expect(await market.getCredits(owner.address)).to.equal(listingPrice);
+    });
+  });
+
+  describe("Private sales", function () {
+    const price = ethers.utils.parseEther("1");
+    let collection;
+
+    beforeEach(async function () {
+      collection = await deployCollection([1]);
+    });
+
+    it("Should only sell to the designated buyer and hide the listing from the public feed", async function () {
+      await expect(market.connect(seller).createPrivateSale(collection.address, 1, price, ethers.constants.AddressZero, ethers.constants.HashZero, { value: listingPrice }))
+        .to.be.revertedWith("Private sale needs a buyer or a Merkle root of buyers");
+      await expect(market.connect(seller).createPrivateSale(collection.address, 1, price, buyer.address, ethers.constants.HashZero, { value: listingPrice }))
+        .to.emit(market, "PrivateSaleCreated")
+        .withArgs(1, buyer.address, seller.address, ethers.constants.HashZero, price);
+
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+      expect((await market.connect(bidder).fetchPrivateSales()).length).to.equal(0);
+      expect((await market.connect(buyer).fetchPrivateSales())[0].tokenId).to.equal(1);
+
+      await expect(market.connect(bidder).createMarketSale(collection.address, 1, { value: price }))
+        .to.be.revertedWith("Item is reserved for another buyer");
+      await market.connect(buyer).createMarketSale(collection.address, 1, { value: price });
+      expect(await collection.ownerOf(1)).to.equal(buyer.address);
+      expect((await market.connect(buyer).fetchPrivateSales()).length).to.equal(0);
+    });
+
+    it("Should sell to any buyer proving membership of the Merkle root", async function () {
+      const leaves = [buyer.address, bidder.address].map((account) => ethers.utils.solidityKeccak256(["address"], [account]));
+      const [low, high] = leaves[0].toLowerCase() < leaves[1].toLowerCase() ? leaves : [leaves[1], leaves[0]];
+      const root = ethers.utils.solidityKeccak256(["bytes32", "bytes32"], [low, high]);
+      await market.connect(seller).createPrivateSale(collection.address, 1, price, ethers.constants.AddressZero, root, { value: listingPrice });
+
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+      expect((await market.connect(bidder).fetchRootGatedPrivateSales())[0].tokenId).to.equal(1);
+      expect(await market.isAllowedBuyer(1, bidder.address, [leaves[0]])).to.equal(true);
+      expect(await market.isAllowedBuyer(1, owner.address, [leaves[0]])).to.equal(false);
+      await expect(market.connect(owner).createPrivateMarketSale(collection.address, 1, [leaves[0]], { value: price }))
+        .to.be.revertedWith("Item is reserved for another buyer");
+      await expect(market.connect(bidder).createMarketSale(collection.address, 1, { value: price }))
+        .to.be.revertedWith("Item is reserved for another buyer");
+
+      await market.connect(bidder).createPrivateMarketSale(collection.address, 1, [leaves[0]], { value: price });
+      expect(await collection.ownerOf(1)).to.equal(bidder.address);
+      expect((await market.fetchRootGatedPrivateSales()).length).to.equal(0);
+    });
+
+    it("Should make the item public again when it is relisted", async function () {
+      await market.connect(seller).createPrivateSale(collection.address, 1, price, buyer.address, ethers.constants.HashZero, { value: listingPrice });
+      await market.connect(seller).cancelListing(collection.address, 1);
+      await market.connect(seller).createMarketItem(collection.address, 1, price, { value: listingPrice });
+
+      expect((await market.fetchMarketItems()).length).to.equal(1);
+      await market.connect(bidder).createMarketSale(collection.address, 1, { value: price });
+      expect(await collection.ownerOf(1)).to.equal(bidder.address);
+    });
+  });
+
+  describe("Collection and trait offers", function () {
+    const price = ethers.utils.parseEther("1");
+    let collection, expiry;
+
+    beforeEach(async function () {
+      collection = await deployCollection([1, 2, 3]);
+      const { timestamp } = await ethers.provider.getBlock("latest");
+      expiry = timestamp + 3600;
+    });
+
+    it("Should escrow the price of every token wanted", async function () {
+      await expect(market.connect(bidder).makeCollectionOffer(collection.address, price, 2, expiry, { value: price }))
+        .to.be.revertedWith("Please submit the price per token times the quantity");
+      await expect(market.connect(bidder).makeCollectionOffer(collection.address, price, 2, expiry, { value: price.mul(2) }))
+        .to.emit(market, "CollectionOfferMade")
+        .withArgs(1, collection.address, bidder.address, price, 2, expiry, ethers.constants.HashZero);
+      expect((await market.fetchCollectionOffers(collection.address)).length).to.equal(1);
+    });
+
+    it("Should let any holder fill a collection offer until its quantity runs out", async function () {
+      await market.connect(bidder).makeCollectionOffer(collection.address, price, 2, expiry, { value: price.mul(2) });
+
+      await expect(market.connect(seller).acceptCollectionOffer(1, 1, [], { value: listingPrice }))
+        .to.emit(market, "CollectionOfferAccepted")
+        .withArgs(1, 1, seller.address, bidder.address, 1, 1);
+      await market.connect(seller).acceptCollectionOffer(1, 2, [], { value: listingPrice });
+      await expect(market.connect(seller).acceptCollectionOffer(1, 3, [], { value: listingPrice }))
+        .to.be.revertedWith("Offer is not active");
+
+      expect(await collection.ownerOf(2)).to.equal(bidder.address);
+      expect(await market.getCredits(seller.address)).to.equal(price.mul(2));
+      expect((await market.connect(bidder).fetchMyNFTs()).length).to.equal(2);
+      expect((await market.fetchCollectionOffers(collection.address)).length).to.equal(0);
+    });
+
+    it("Should only accept tokens proven to have the offered trait", async function () {
+      const leaves = [1, 3].map((tokenId) => ethers.utils.solidityKeccak256(["uint256"], [tokenId]));
+      const [low, high] = leaves[0].toLowerCase() < leaves[1].toLowerCase() ? leaves : [leaves[1], leaves[0]];
+      const traitRoot = ethers.utils.solidityKeccak256(["bytes32", "bytes32"], [low, high]);
+      await market.connect(bidder).makeTraitOffer(collection.address, price, 2, expiry, traitRoot, { value: price.mul(2) });
+
+      await expect(market.connect(seller).acceptCollectionOffer(1, 2, [leaves[1]], { value: listingPrice }))
+        .to.be.revertedWith("Token does not have the offered trait");
+      await market.connect(seller).acceptCollectionOffer(1, 3, [leaves[0]], { value: listingPrice });
+      expect(await collection.ownerOf(3)).to.equal(bidder.address);
+    });
+
+    it("Should refund the escrow for the tokens still wanted on cancellation", async function () {
+      await market.connect(bidder).makeCollectionOffer(collection.address, price, 3, expiry, { value: price.mul(3) });
+      await market.connect(seller).acceptCollectionOffer(1, 1, [], { value: listingPrice });
+
+      await expect(market.connect(seller).cancelCollectionOffer(1))
+        .to.be.revertedWith("Only the bidder can cancel an offer");
+      await expect(market.connect(bidder).cancelCollectionOffer(1))
+        .to.emit(market, "CollectionOfferCancelled")
+        .withArgs(1, bidder.address, price.mul(2));
+      expect(await market.getCredits(bidder.address)).to.equal(price.mul(2));
+      expect((await market.fetchCollectionOffers(collection.address)).length).to.equal(0);
+    });
+
+    it("Should only list the open offers of the collection asked for", async function () {
+      const other = await deployCollection([1]);
+      await market.connect(bidder).makeCollectionOffer(collection.address, price, 1, expiry, { value: price });
+      await market.connect(bidder).makeCollectionOffer(other.address, price, 1, expiry, { value: price });
+      await market.connect(bidder).makeCollectionOffer(collection.address, price, 1, expiry, { value: price });
+      await market.connect(bidder).cancelCollectionOffer(1);
+
+      const offers = await market.fetchCollectionOffers(collection.address);
+      expect(offers.map((offer) => offer.offerId.toNumber())).to.deep.equal([3]);
+    });
+  });
+
+  describe("Bundles", function () {
+    const price = ethers.utils.parseEther("3");
+    let collection;
+
+    beforeEach(async function () {
+      collection = await deployCollection([1, 2, 3]);
+    });
+
+    it("Should escrow every token and show the bundle as a single listing", async function () {
+      await expect(market.connect(seller).createBundleListing(collection.address, [1], price, { value: listingPrice }))
+        .to.be.revertedWith("Bundle must contain at least two tokens");
+      await expect(market.connect(seller).createBundleListing(collection.address, [1, 2, 3], price, { value: listingPrice }))
+        .to.emit(market, "BundleListingCreated")
+        .withArgs(1, collection.address, seller.address, [1, 2, 3], price);
+
+      expect(await collection.balanceOf(market.address)).to.equal(3);
+      const items = await market.fetchMarketItems();
+      expect(items.length).to.equal(1);
+      expect(items[0].isBundle).to.equal(true);
+      expect(items[0].quantity).to.equal(3);
+      expect((await market.fetchBundleTokenIds(1)).map((tokenId) => tokenId.toNumber())).to.deep.equal([1, 2, 3]);
+    });
+
+    it("Should only sell the bundle as a whole", async function () {
+      await market.connect(seller).createBundleListing(collection.address, [1, 2, 3], price, { value: listingPrice });
+      await expect(market.connect(buyer).createMarketSale(collection.address, 2, { value: price }))
+        .to.be.revertedWith("Item is not listed");
+      await expect(market.connect(buyer).buyBundle(1, { value: price.sub(1) }))
+        .to.be.revertedWith("Please submit the asking price in order to complete the purchase");
+
+      await expect(market.connect(buyer).buyBundle(1, { value: price }))
+        .to.emit(market, "MarketItemSold")
+        .withArgs(1, seller.address, buyer.address, collection.address, 1, ethers.constants.AddressZero, price, 0, 0);
+      expect(await collection.balanceOf(buyer.address)).to.equal(3);
+      expect(await market.getCredits(seller.address)).to.equal(price);
+      const owned = await market.connect(buyer).fetchMyNFTs();
+      expect(owned.map((item) => item.tokenId.toNumber())).to.deep.equal([1, 2, 3]);
+      expect(owned.every((item) => item.sold && !item.isBundle)).to.equal(true);
+      expect((await market.fetchMarketItem(1)).owner).to.equal(ethers.constants.AddressZero);
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+    });
+
+    it("Should return every token to the seller on cancellation", async function () {
+      await market.connect(seller).createBundleListing(collection.address, [1, 2], price, { value: listingPrice });
+      await expect(market.connect(buyer).cancelBundleListing(1))
+        .to.be.revertedWith("Only item seller can perform this operation");
+      await market.connect(seller).cancelBundleListing(1);
+
+      expect(await collection.balanceOf(seller.address)).to.equal(3);
+      expect((await market.connect(seller).fetchMyNFTs()).map((item) => item.tokenId.toNumber())).to.deep.equal([1, 2]);
+      expect((await market.fetchMarketItems()).length).to.equal(0);
+      expect(await market.getCredits(owner.address)).to.equal(listingPrice);
+    });
+
+    it("Should pay the royalty of every token on its share of the bundle price", async function () {
+      await market.connect(bidder).createTokenWithRoyalty("https://www.mytokenlocation.com", price, 1000, { value: listingPrice });
+      await market.connect(bidder).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      for (const tokenId of [1, 2]) {
+        await market.connect(bidder).cancelListing(nft.address, tokenId);
+        await nft.connect(bidder).transferFrom(bidder.address, seller.address, tokenId);
+      }
+
+      await market.connect(seller).createBundleListing(nft.address, [1, 2], price, { value: listingPrice });
+      const royaltyAmount = price.div(2).mul(10).div(100).add(price.div(2).mul(5).div(100));
+      await expect(market.connect(buyer).buyBundle(3, { value: price }))
+        .to.emit(market, "MarketItemSold")
+        .withArgs(3, seller.address, buyer.address, nft.address, 1, ethers.constants.AddressZero, price, 0, royaltyAmount);
+      expect(await market.getCredits(bidder.address)).to.equal(royaltyAmount);
+      expect(await market.getCredits(seller.address)).to.equal(price.sub(royaltyAmount));
+    });
+
+    it("Should pay each token's royalty to its own creator", async function () {
+      await market.connect(bidder).createTokenWithRoyalty("https://www.mytokenlocation.com", price, 1000, { value: listingPrice });
+      await market.connect(owner).createToken("https://www.mytokenlocation.com", price, { value: listingPrice });
+      await market.connect(bidder).cancelListing(nft.address, 1);
+      await market.connect(owner).cancelListing(nft.address, 2);
+      await nft.connect(bidder).transferFrom(bidder.address, seller.address, 1);
+      await nft.connect(owner).transferFrom(owner.address, seller.address, 2);
+
+      await market.connect(seller).createBundleListing(nft.address, [1, 2], price, { value: listingPrice });
+      const ownerCredits = await market.getCredits(owner.address);
+      const bidderRoyalty = price.div(2).mul(10).div(100);
+      const ownerRoyalty = price.div(2).mul(5).div(100);
+      await expect(market.connect(buyer).buyBundle(3, { value: price }))
+        .to.emit(market, "RoyaltyPaid")
+        .withArgs(3, bidder.address, bidderRoyalty)
+        .and.to.emit(market, "RoyaltyPaid")
+        .withArgs(3, owner.address, ownerRoyalty);
+      expect(await market.getCredits(bidder.address)).to.equal(bidderRoyalty);
+      expect(await market.getCredits(owner.address)).to.equal(ownerCredits.add(ownerRoyalty));
+      expect(await market.getCredits(seller.address)).to.equal(price.sub(bidderRoyalty).sub(ownerRoyalty));
+    });
+  });
+});